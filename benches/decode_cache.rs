@@ -0,0 +1,48 @@
+use std::time::{Duration, Instant};
+
+use vm::cpu::{CPU, WordType};
+use vm::instruction::Instruction;
+use vm::memory::Memory;
+
+const STEPS: u32 = 10_000_000;
+
+// A tight counting loop: r1 and r3 are bumped by r2 (loaded with 1) forever.
+fn program() -> Memory {
+  let code = [
+    Instruction::LoadRelative(6),
+    Instruction::Move(2, 0),
+    Instruction::Add(1, 2),
+    Instruction::Add(3, 2),
+    Instruction::BitXor(4, 1),
+    Instruction::JumpRelative(-4, 0),
+  ];
+
+  let mut memory = Memory::new(64);
+  for (pos, instruction) in code.iter().enumerate() {
+    memory.set(pos as WordType, WordType::from(instruction)).unwrap();
+  }
+  memory.set(code.len() as WordType, 1).unwrap();
+  memory
+}
+
+fn run(cached: bool) -> Duration {
+  let mut cpu = CPU::new(program(), 0, 16);
+  if cached {
+    cpu.enable_decode_cache();
+  }
+
+  let start = Instant::now();
+  for _ in 0..STEPS {
+    cpu.step().unwrap();
+  }
+  start.elapsed()
+}
+
+fn main() {
+  let uncached = run(false);
+  let cached = run(true);
+
+  println!("uncached: {:>10?} ({:.2} ns/step)", uncached, uncached.as_nanos() as f64 / STEPS as f64);
+  println!("cached:   {:>10?} ({:.2} ns/step)", cached, cached.as_nanos() as f64 / STEPS as f64);
+  println!("speedup:  {:.2}x", uncached.as_secs_f64() / cached.as_secs_f64());
+}
@@ -0,0 +1,57 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use vm::cpu::WordType;
+use vm::instruction::Instruction;
+use vm::machine::atomic_memory::AtomicMemory;
+use vm::machine::memory::{Backend, Memory};
+use vm::machine::processor::Processor;
+
+const STEPS: usize = 2_000_000;
+
+// Same counting loop as the other benchmarks, with every processor running the one copy.
+fn program(memory: &impl Backend) {
+  let code = [
+    Instruction::LoadRelative(6),
+    Instruction::Move(2, 0),
+    Instruction::Add(1, 2),
+    Instruction::Add(3, 2),
+    Instruction::BitXor(4, 1),
+    Instruction::JumpRelative(-4, 0),
+  ];
+
+  for (pos, instruction) in code.iter().enumerate() {
+    memory.set(pos as WordType, WordType::from(instruction)).unwrap();
+  }
+  memory.set(code.len() as WordType, 1).unwrap();
+}
+
+fn run<M: Backend + Clone + Send>(memory: M, processors: usize) -> Duration {
+  program(&memory);
+  let start = Instant::now();
+  thread::scope(|scope| {
+    for _ in 0..processors {
+      let memory = memory.clone();
+      scope.spawn(move || {
+        let mut processor = Processor::new(memory, 16, 0);
+        for _ in 0..STEPS {
+          processor.step().unwrap();
+        }
+      });
+    }
+  });
+  start.elapsed()
+}
+
+fn main() {
+  for processors in &[1, 4, 16] {
+    let locked = run(Memory::new(64), *processors);
+    let atomic = run(AtomicMemory::new(64), *processors);
+    let steps = (STEPS * processors) as f64;
+
+    println!("{} processor(s):", processors);
+    println!("  rwlock: {:>10?} ({:.2} ns/step)", locked, locked.as_nanos() as f64 / steps);
+    println!("  atomic: {:>10?} ({:.2} ns/step)", atomic, atomic.as_nanos() as f64 / steps);
+    println!("  speedup: {:.2}x", locked.as_secs_f64() / atomic.as_secs_f64());
+  }
+}
@@ -0,0 +1,54 @@
+use std::time::{Duration, Instant};
+
+use vm::cpu::{CPU, WordType};
+use vm::instruction::Instruction;
+use vm::memory::Memory;
+use vm::threaded::ThreadedCPU;
+
+const STEPS: usize = 10_000_000;
+
+// Same counting loop as the decode cache benchmark.
+fn program() -> Memory {
+  let code = [
+    Instruction::LoadRelative(6),
+    Instruction::Move(2, 0),
+    Instruction::Add(1, 2),
+    Instruction::Add(3, 2),
+    Instruction::BitXor(4, 1),
+    Instruction::JumpRelative(-4, 0),
+  ];
+
+  let mut memory = Memory::new(64);
+  for (pos, instruction) in code.iter().enumerate() {
+    memory.set(pos as WordType, WordType::from(instruction)).unwrap();
+  }
+  memory.set(code.len() as WordType, 1).unwrap();
+  memory
+}
+
+fn interpreted() -> Duration {
+  let mut cpu = CPU::new(program(), 0, 16);
+  let start = Instant::now();
+  for _ in 0..STEPS {
+    cpu.step().unwrap();
+  }
+  start.elapsed()
+}
+
+fn threaded() -> Duration {
+  let mut cpu = ThreadedCPU::new(CPU::new(program(), 0, 16));
+  let start = Instant::now();
+  let (executed, res) = cpu.run(STEPS);
+  let elapsed = start.elapsed();
+  assert_eq!((STEPS, Ok(())), (executed, res));
+  elapsed
+}
+
+fn main() {
+  let interpreted = interpreted();
+  let threaded = threaded();
+
+  println!("interpreted: {:>10?} ({:.2} ns/step)", interpreted, interpreted.as_nanos() as f64 / STEPS as f64);
+  println!("threaded:    {:>10?} ({:.2} ns/step)", threaded, threaded.as_nanos() as f64 / STEPS as f64);
+  println!("speedup:     {:.2}x", interpreted.as_secs_f64() / threaded.as_secs_f64());
+}
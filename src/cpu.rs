@@ -1,30 +1,164 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::energy::{CostTable, Meter};
 use crate::memory::*;
 use crate::instruction::*;
+use crate::interpreter::Interpreter;
+use crate::rng::Rng;
+use crate::shared_arc::SharedArc;
 
 pub type WordType = u16;
-type ConversionType = i32;
+pub(crate) type ConversionType = i32;
+pub(crate) type SignedType = i16;
 
 pub const WORD_MAX: WordType = std::u16::MAX;
 
-const FLAGS: usize = 13;
-const PC: usize = 14;
-const STACK_POINTER: usize = 15;
+pub(crate) const FLAGS: usize = 13;
+pub(crate) const PC: usize = 14;
+pub(crate) const STACK_POINTER: usize = 15;
 
-const FLAG_OVERFLOW: WordType = 0x0001;
-const FLAG_COMPARISON: WordType = 0x0002;
+pub(crate) const FLAG_OVERFLOW: WordType = 0x0001;
+pub(crate) const FLAG_COMPARISON: WordType = 0x0002;
+// Like FLAG_OVERFLOW, but for the operands read as two's complement.
+pub(crate) const FLAG_SIGNED_OVERFLOW: WordType = 0x0004;
+// Set by arithmetic and bitwise instructions from their result.
+pub(crate) const FLAG_ZERO: WordType = 0x0008;
+pub(crate) const FLAG_NEGATIVE: WordType = 0x0010;
+// Carry out of or borrow into the top bit, the high half of a product or the last bit shifted out.
+pub(crate) const FLAG_CARRY: WordType = 0x0020;
+// While set, faults and unclaimed interrupts go to the guest's vector table instead of the host.
+// Cleared on the way into a handler and restored by RETI.
+pub(crate) const FLAG_INTERRUPTS: WordType = 0x0040;
+// Masks the timer while clear. An expiry that finds it masked waits until it is set again.
+pub(crate) const FLAG_TIMER: WordType = 0x0080;
 
-pub struct CPU {
+// Interrupt numbers serviced by the host. See `population` for the register conventions.
+pub const INT_REPRODUCE: u8 = 4;
+
+// Serviced by the CPU itself: r0 gets a word from the machine's own PRNG, below r1 unless r1 is zero.
+pub const INT_RANDOM: u8 = 5;
+// Serviced by the host like INT_REPRODUCE, see `population` for the register conventions.
+pub const INT_INSPECT: u8 = 6;
+pub const INT_DECIDE: u8 = 7;
+// Serviced by `PairMachine`, see `pair` for the register conventions.
+pub const INT_PAIR_SEND: u8 = 8;
+pub const INT_PAIR_RECEIVE: u8 = 9;
+
+// Serviced by the CPU itself: swaps r0 with the base of the vector table.
+pub const INT_VECTORS: u8 = 10;
+// Serviced by the CPU itself: copies the base of the vector table into r0 and leaves it in place.
+pub const INT_VECTORS_READ: u8 = 15;
+// Serviced by the CPU itself: swaps r0 with the timer period, restarting the timer. 0 stops it.
+pub const INT_TIMER: u8 = 11;
+
+// Serviced by the CPU itself, see `CPU::fork` and friends. Threads are named by ids handed out from
+// 1, the thread a machine starts with. With the stack in main memory, INT_FORK takes the base of the
+// new thread's stack from r1.
+pub const INT_FORK: u8 = 12;
+pub const INT_JOIN: u8 = 13;
+pub const INT_KILL: u8 = 14;
+
+// Vector table entries, each holding a handler address or 0 for none. Software interrupt `n` uses
+// entry `VEC_SOFTWARE + n`, so a table only needs to reach as far as the highest one in use.
+pub const VEC_STACK_OVERFLOW: WordType = 0;
+pub const VEC_STACK_UNDERFLOW: WordType = 1;
+pub const VEC_MEMORY: WordType = 2;
+pub const VEC_JUMP_CONDITION: WordType = 3;
+pub const VEC_DIVIDE_BY_ZERO: WordType = 4;
+pub const VEC_TIMER: WordType = 5;
+pub const VEC_SOFTWARE: WordType = 8;
+
+// A read-only copy of another machine's memory, mapped right after the end of this one's.
+struct Window {
+  words: Vec<WordType>,
+  cycles: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Growth {
+  Up,
+  Down,
+}
+
+// Where PUSH/POP and friends keep their words. SP counts the words on the stack either way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StackMode {
+  // A memory of its own with this many words, out of reach of loads and stores.
+  Separate(WordType),
+  // `size` words of the address space starting at `base`, so loads and stores see them too. Stack
+  // word `n` lives at `base + n` growing up or `base - n` growing down. `CPU::with_stack` cuts the
+  // region short where it would leave main memory.
+  InMemory { base: WordType, size: WordType, growth: Growth },
+}
+
+// Expires every `period` instructions, counting the ones executed since it was last set.
+#[derive(Default)]
+struct Timer {
+  period: WordType,
+  remaining: WordType,
+  pending: bool,
+}
+
+// A hardware thread waiting for its turn.
+struct Thread {
+  id: WordType,
   registers: [WordType; 16],
   stack: Memory,
-  memory: Memory
+  stack_mode: StackMode,
+}
+
+pub struct CPU {
+  pub(crate) registers: [WordType; 16],
+  pub(crate) stack: Memory,
+  stack_mode: StackMode,
+  pub(crate) memory: Memory,
+  interrupt: Option<u8>,
+  meter: Option<Meter>,
+  rng: Rng,
+  window: Option<Window>,
+  shared: Option<SharedArc<Memory>>,
+  vectors: WordType,
+  timer: Timer,
+  // The running thread is the one in `registers`, the rest take turns after it.
+  thread: WordType,
+  threads: VecDeque<Thread>,
+  next_thread: WordType,
+  max_threads: usize,
+  exiting: bool,
+  // Interrupt numbers the host services, one bit each. The rest can go to the guest.
+  claimed: [u64; 4]
 }
 
 impl CPU {
   pub fn new(memory: Memory, pc: WordType, stack_size: WordType) -> Self {
+    CPU::with_stack(memory, pc, StackMode::Separate(stack_size))
+  }
+
+  pub fn with_stack(memory: Memory, pc: WordType, stack_mode: StackMode) -> Self {
+    let stack_mode = fit_stack(stack_mode, memory.len());
+    let stack_size = match stack_mode {
+      StackMode::Separate(size) => size,
+      StackMode::InMemory { .. } => 0
+    };
     let mut this = CPU {
       registers: [0; 16],
       stack: Memory::new(stack_size),
-      memory
+      stack_mode,
+      memory,
+      interrupt: None,
+      meter: None,
+      rng: Rng::new(0),
+      window: None,
+      shared: None,
+      vectors: 0,
+      timer: Timer::default(),
+      thread: 1,
+      threads: VecDeque::new(),
+      next_thread: 2,
+      max_threads: 1,
+      exiting: false,
+      claimed: [0; 4]
     };
 
     this.registers[PC] = pc;
@@ -32,13 +166,357 @@ impl CPU {
     this
   }
 
+  pub fn stack_mode(&self) -> StackMode {
+    self.stack_mode
+  }
+
   pub fn borrow_mem(&mut self) -> &mut Memory {
     &mut self.memory
   }
 
+  pub fn enable_decode_cache(&mut self) {
+    self.memory.enable_decode_cache();
+  }
+
+  pub fn register(&self, reg: usize) -> WordType {
+    self.registers[reg]
+  }
+
+  pub fn set_register(&mut self, reg: usize, value: WordType) {
+    self.registers[reg] = value;
+  }
+
+  // Every instruction from now on is paid for out of `energy` according to `costs`. A CPU that can't
+  // pay stops with `CPUErr::OutOfEnergy` until it is granted more.
+  pub fn meter(&mut self, energy: u64, costs: Arc<CostTable>) {
+    self.meter = Some(Meter { energy, costs });
+  }
+
+  pub fn costs(&self) -> Option<&Arc<CostTable>> {
+    self.meter.as_ref().map(|meter| &meter.costs)
+  }
+
+  // None if the CPU is not metered and runs for free.
+  pub fn energy(&self) -> Option<u64> {
+    self.meter.as_ref().map(|meter| meter.energy)
+  }
+
+  pub fn grant(&mut self, amount: u64) {
+    if let Some(meter) = &mut self.meter {
+      meter.energy = meter.energy.saturating_add(amount);
+    }
+  }
+
+  // Removes up to `amount` energy and returns how much was actually taken.
+  pub fn take_energy(&mut self, amount: u64) -> u64 {
+    match &mut self.meter {
+      Some(meter) => {
+        let taken = amount.min(meter.energy);
+        meter.energy -= taken;
+        taken
+      },
+      None => 0
+    }
+  }
+
+  // Makes `words` readable, but not writable, from the end of memory on for the next `cycles`
+  // instructions. Returns the address the window starts at.
+  pub fn map_window(&mut self, words: Vec<WordType>, cycles: u64) -> WordType {
+    self.window = Some(Window { words, cycles });
+    self.memory.len()
+  }
+
+  pub fn unmap_window(&mut self) {
+    self.window = None;
+  }
+
+  pub fn window(&self) -> Option<&[WordType]> {
+    self.window.as_ref().map(|window| &window.words[..])
+  }
+
+  // Maps `region` at the very top of the address space, readable and writable. Whoever else holds
+  // the region sees the writes. Returns the address the region starts at.
+  pub fn attach_shared(&mut self, region: SharedArc<Memory>) -> WordType {
+    let base = shared_base(&region);
+    self.shared = Some(region);
+    base
+  }
+
+  // Where stack word `slot` lives in the address space, if the stack lives there at all.
+  pub(crate) fn stack_address(&self, slot: WordType) -> Option<WordType> {
+    match self.stack_mode {
+      StackMode::InMemory { base, size, growth } if slot < size => match growth {
+        Growth::Up => Some(base.wrapping_add(slot)),
+        Growth::Down => Some(base.wrapping_sub(slot))
+      },
+      _ => None
+    }
+  }
+
+  // Main memory a push, poke or software interrupt would write to, for engines that need to notice
+  // code being written.
+  pub(crate) fn stack_writes(&self, instruction: Instruction) -> Vec<WordType> {
+    let top = self.registers[STACK_POINTER];
+    let slots = match instruction {
+      Instruction::PushRegister(_) => top..top.saturating_add(1),
+      Instruction::PushRegisters => top..top.saturating_add(FLAGS as WordType),
+      // A software interrupt the guest handles pushes PC and FLAGS.
+      Instruction::Interrupt(_) => top..top.saturating_add(2),
+      Instruction::Poke(depth, _) => match self.stack_slot(depth) {
+        Ok(slot) => slot..(slot + 1),
+        Err(_) => 0..0
+      },
+      _ => 0..0
+    };
+    slots.filter_map(|slot| self.stack_address(slot)).collect()
+  }
+
+  // Counts down the window and the timer, once per instruction.
+  pub(crate) fn tick(&mut self) {
+    if let Some(window) = &mut self.window {
+      if window.cycles == 0 {
+        self.window = None;
+      } else {
+        window.cycles -= 1;
+      }
+    }
+
+    if self.timer.period != 0 {
+      self.timer.remaining -= 1;
+      if self.timer.remaining == 0 {
+        self.timer.remaining = self.timer.period;
+        self.timer.pending = true;
+      }
+    }
+  }
+
+  pub fn vectors(&self) -> WordType {
+    self.vectors
+  }
+
+  pub fn set_vectors(&mut self, base: WordType) {
+    self.vectors = base;
+  }
+
+  // Whether the timer has gone off without being delivered yet, which is all `trap` has to do after
+  // an instruction that didn't fault.
+  pub(crate) fn timer_expired(&self) -> bool {
+    self.timer.pending
+  }
+
+  pub fn timer(&self) -> WordType {
+    self.timer.period
+  }
+
+  pub fn set_timer(&mut self, period: WordType) {
+    self.timer = Timer { period, remaining: period, pending: false };
+  }
+
+  // How many threads may run at once, counting the first one. 1 means INT_FORK always fails.
+  pub fn set_max_threads(&mut self, max_threads: usize) {
+    self.max_threads = max_threads.max(1);
+  }
+
+  pub fn max_threads(&self) -> usize {
+    self.max_threads
+  }
+
+  // Id of the running thread.
+  pub fn thread(&self) -> WordType {
+    self.thread
+  }
+
+  pub fn threads(&self) -> usize {
+    self.threads.len() + 1
+  }
+
+  pub(crate) fn is_threaded(&self) -> bool {
+    !self.threads.is_empty()
+  }
+
+  // Starts a thread at `pc` with a copy of the running thread's registers, r0 cleared and an empty
+  // stack of its own. Returns its id, or None at the limit. A stack in main memory is placed at
+  // `stack_base`, sized and growing like the running thread's.
+  pub fn fork(&mut self, pc: WordType, stack_base: WordType) -> Option<WordType> {
+    if self.threads() >= self.max_threads {
+      return None;
+    }
+    let id = self.next_thread;
+    self.next_thread = self.next_thread.wrapping_add(1);
+
+    let mut registers = self.registers;
+    registers[0] = 0;
+    registers[PC] = pc;
+    registers[STACK_POINTER] = 0;
+    let (stack, stack_mode) = match self.stack_mode {
+      StackMode::Separate(size) => (Memory::new(size), StackMode::Separate(size)),
+      StackMode::InMemory { size, growth, .. } => {
+        let mode = StackMode::InMemory { base: stack_base, size, growth };
+        (Memory::new(0), fit_stack(mode, self.memory.len()))
+      }
+    };
+    self.threads.push_back(Thread { id, registers, stack, stack_mode });
+    Some(id)
+  }
+
+  // Whether thread `id` is still around.
+  pub fn is_running(&self, id: WordType) -> bool {
+    (id == self.thread && !self.exiting) || self.threads.iter().any(|thread| thread.id == id)
+  }
+
+  // Stops thread `id`, where 0 is the running one. The last thread can't be killed.
+  pub fn kill(&mut self, id: WordType) -> bool {
+    if id == 0 || id == self.thread {
+      if self.exiting || self.threads.is_empty() {
+        return false;
+      }
+      self.exiting = true;
+      return true;
+    }
+    let before = self.threads.len();
+    self.threads.retain(|thread| thread.id != id);
+    self.threads.len() != before
+  }
+
+  // Hands the CPU to the next thread in line, dropping the running one if it has been killed.
+  pub(crate) fn schedule(&mut self) {
+    let mut next = match self.threads.pop_front() {
+      Some(next) => next,
+      None => return
+    };
+    std::mem::swap(&mut self.registers, &mut next.registers);
+    std::mem::swap(&mut self.stack, &mut next.stack);
+    std::mem::swap(&mut self.stack_mode, &mut next.stack_mode);
+    std::mem::swap(&mut self.thread, &mut next.id);
+    if self.exiting {
+      self.exiting = false;
+    } else {
+      self.threads.push_back(next);
+    }
+  }
+
+  // Keeps interrupt `number` with the host even when the guest has handlers enabled.
+  pub fn claim(&mut self, number: u8) {
+    self.claimed[(number / 64) as usize] |= 1 << (number % 64);
+  }
+
+  pub fn is_claimed(&self, number: u8) -> bool {
+    self.claimed[(number / 64) as usize] & (1 << (number % 64)) != 0
+  }
+
+  // Hands a fault of the instruction at `pc` to the guest, if it has a handler for it. Whatever the
+  // guest can't take goes back to the caller unchanged. Without a fault, an expired timer gets its
+  // turn instead. Returns the main memory written delivering either, like `stack_writes`.
+  pub(crate) fn trap(&mut self, pc: WordType, res: Result<(), CPUErr>) -> Result<Vec<WordType>, CPUErr> {
+    let vector = match &res {
+      Ok(()) => {
+        if self.timer.pending && self.registers[FLAGS] & FLAG_TIMER != 0 {
+          if let Some(written) = self.deliver(VEC_TIMER, self.registers[PC]) {
+            self.timer.pending = false;
+            return Ok(written);
+          }
+        }
+        return Ok(Vec::new());
+      },
+      Err(CPUErr::StackOverflow) => VEC_STACK_OVERFLOW,
+      Err(CPUErr::StackUnderflow) => VEC_STACK_UNDERFLOW,
+      Err(CPUErr::MemoryErr(_)) => VEC_MEMORY,
+      Err(CPUErr::InvalidJumpCondition(_)) => VEC_JUMP_CONDITION,
+      Err(CPUErr::DivideByZero) => VEC_DIVIDE_BY_ZERO,
+      Err(_) => return res.map(|_| Vec::new())
+    };
+    match self.deliver(vector, pc) {
+      Some(written) => Ok(written),
+      None => res.map(|_| Vec::new())
+    }
+  }
+
+  // Pushes `pc` and FLAGS and enters the handler for `vector`. Returns where in main memory the
+  // push went, or nothing if the guest can't take the interrupt.
+  fn deliver(&mut self, vector: WordType, pc: WordType) -> Option<Vec<WordType>> {
+    if self.registers[FLAGS] & FLAG_INTERRUPTS == 0 {
+      return None;
+    }
+    let handler = match self.load(self.vectors.wrapping_add(vector)) {
+      Ok(0) | Err(_) => return None,
+      Ok(handler) => handler
+    };
+
+    let top = self.registers[STACK_POINTER];
+    if self.stack_set(top, pc).is_err() || self.stack_set(top.wrapping_add(1), self.registers[FLAGS]).is_err() {
+      return None;
+    }
+    self.registers[STACK_POINTER] = top + 2;
+    self.set_flag(FLAG_INTERRUPTS, false);
+    self.registers[PC] = handler;
+    Some((top..top + 2).filter_map(|slot| self.stack_address(slot)).collect())
+  }
+
+  // The interrupt raised by the last `INT`, if the host has not picked it up yet.
+  pub fn interrupt(&self) -> Option<u8> {
+    self.interrupt
+  }
+
+  pub fn take_interrupt(&mut self) -> Option<u8> {
+    self.interrupt.take()
+  }
+
+  pub fn has_interrupt(&self) -> bool {
+    self.interrupt.is_some()
+  }
+
+  // The PRNG behind INT_RANDOM, e.g. to checkpoint its state.
+  pub fn rng(&self) -> &Rng {
+    &self.rng
+  }
+
+  // Replaces the PRNG behind INT_RANDOM, e.g. with one restored from a checkpoint.
+  pub fn set_rng(&mut self, rng: Rng) {
+    self.rng = rng;
+  }
+
+  // Pays for `instruction`, which is about to run.
+  pub(crate) fn charge(&mut self, instruction: Instruction) -> Result<(), CPUErr> {
+    if let Some(meter) = &mut self.meter {
+      // Invalid words encode as NOP but are charged for their own opcode.
+      let opcode = match instruction {
+        Instruction::Invalid(word) => word,
+        other => WordType::from(other)
+      };
+      let mut cost = meter.costs.cost(opcode);
+      // Block operations also pay for every word they go over.
+      let block = matches!(
+        instruction,
+        Instruction::CopyBlock(_, _) | Instruction::FillBlock(_, _) | Instruction::CompareBlock(_, _)
+      );
+      if block {
+        cost = cost.saturating_add(meter.costs.word_cost().saturating_mul(self.registers[0] as u64));
+      }
+      if meter.energy < cost {
+        return Err(CPUErr::OutOfEnergy);
+      }
+      meter.energy -= cost;
+    }
+    Ok(())
+  }
+
+  // Runs one instruction of the next thread in line.
   pub fn step(&mut self) -> Result<(), CPUErr> {
-    let res = match self.memory.get(self.registers[PC].clone()) {
-      Ok(instruction) => self.do_instruction(instruction),
+    self.schedule();
+    self.execute().map(|_| ())
+  }
+
+  // Runs one instruction of the current thread. Returns the main memory written delivering an
+  // interrupt, see `trap`.
+  pub(crate) fn execute(&mut self) -> Result<Vec<WordType>, CPUErr> {
+    let pc = self.registers[PC];
+    let res = match self.memory.decode(self.registers[PC]) {
+      Ok(instruction) => {
+        // Running dry leaves PC on the instruction that could not be paid for.
+        self.charge(instruction)?;
+        self.tick();
+        self.do_instruction(instruction)
+      },
       Err(err) => {
         match err {
           any => {
@@ -50,326 +528,244 @@ impl CPU {
     };
 
     self.registers[PC] = self.registers[PC].wrapping_add(1);
-    res
+    self.trap(pc, res)
   }
 
-  fn do_instruction(&mut self, instruction: WordType) -> Result<(), CPUErr> {
-    match Instruction::from(instruction) {
-      Instruction::PushRegister(reg) => {
-        // Attempt to push the value onto the stack
-        match self.stack.set(self.registers[STACK_POINTER], self.registers[reg as usize]) {
-          // Valid stack position
-          Ok(()) => {
+}
 
-            // Increment the stack pointer
-            self.registers[STACK_POINTER] += 1;
-            Ok(())
-          },
+impl Interpreter for CPU {
+  fn registers(&self) -> &[WordType; 16] {
+    &self.registers
+  }
 
-          // Handle a stack overflow
-          Err(_) => Err(CPUErr::StackOverflow)
-        }
-      },
-      Instruction::PopRegister(reg) => {
-        match self.registers[STACK_POINTER].checked_sub(1) {
-          // Valid stack position
-          Some(pos) => {
-            // Save stack position
-            self.registers[STACK_POINTER] = pos;
-
-            // Retrieve the stack value and put it on the register
-            match self.stack.get(pos) {
-              Ok(value) => {
-                self.registers[reg as usize] = value;
-                Ok(())
-              },
-              _ => Err(CPUErr::Unreachable(String::from("This should never happen because we are already checking that we are within the stack boundaries")))
-            }
-          },
-
-          // Handle a stack underflow
-          None => Err(CPUErr::StackUnderflow)
-        }
-      },
-      Instruction::PushRegisters => {
-        match self.stack.set_range(self.registers[STACK_POINTER], &self.registers[0..FLAGS]) {
-          Ok(()) => {
-            self.registers[STACK_POINTER] += FLAGS as WordType;
-            Ok(())
-          },
-          _ => Err(CPUErr::StackOverflow)
-        }
-      },
-      Instruction::PopRegisters => {
-        match self.registers[STACK_POINTER].checked_sub(FLAGS as WordType) {
-          Some(pos) => {
-            self.registers[STACK_POINTER] = pos;
-            match self.stack.get_range(pos, FLAGS as WordType) {
-              Ok(regs) => {
-                &self.registers[0..FLAGS].clone_from_slice(regs);
-                Ok(())
-              },
-              _ => Err(CPUErr::Unreachable(String::from("This should never happen because we are already checking that we are within the stack boundaries")))
-            }
-          },
-          _ => Err(CPUErr::StackUnderflow)
-        }
-      },
-      Instruction::Move(into, from) => {
-        self.registers[into as usize] = self.registers[from as usize];
-        Ok(())
-      },
-      Instruction::Load(into, src) => {
-        let pointer: WordType = self.registers[src as usize];
-        match self.memory.get(pointer) {
-          Ok(value) => {
-            self.registers[into as usize] = value;
-            Ok(())
-          },
-          Err(err) => Err(CPUErr::MemoryErr(err))
-        }
-      },
-      Instruction::Save(into, from) => {
-        match self.memory.set(self.registers[into as usize], self.registers[from as usize]) {
-          Ok(()) => Ok(()),
-          Err(err) => Err(CPUErr::MemoryErr(err))
-        }
-      },
-      Instruction::Add(into, from) => {
-        let val1 = self.registers[into as usize];
-        let val2 = self.registers[from as usize];
-        let (result, overflow) = val1.overflowing_add(val2);
-        self.registers[into as usize] = result;
-
-        // Update the OVERFLOW flag
-        if overflow {
-          self.registers[FLAGS] = self.registers[FLAGS] | FLAG_OVERFLOW;
-        } else {
-          self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_OVERFLOW;
-        }
-        Ok(())
-      },
-      Instruction::Subtract(into, from) => {
-        let val1 = self.registers[into as usize];
-        let val2 = self.registers[from as usize];
-        let (result, overflow) = val1.overflowing_sub(val2);
-        self.registers[into as usize] = result;
-
-        // Update the OVERFLOW flag
-        if overflow {
-          self.registers[FLAGS] = self.registers[FLAGS] | FLAG_OVERFLOW;
-        } else {
-          self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_OVERFLOW;
-        }
-        Ok(())
+  fn registers_mut(&mut self) -> &mut [WordType; 16] {
+    &mut self.registers
+  }
+
+  fn fetch(&self, pos: WordType) -> Result<WordType, MemoryErr> {
+    self.memory.get(pos)
+  }
+
+  fn load(&self, pos: WordType) -> Result<WordType, MemoryErr> {
+    let err = match self.memory.get(pos) {
+      Ok(value) => return Ok(value),
+      Err(err) => err
+    };
+
+    let window = self.window.as_ref().and_then(|window| {
+      (pos as usize).checked_sub(self.memory.len() as usize)
+        .and_then(|offset| window.words.get(offset).copied())
+    });
+    let shared = || self.shared.as_ref().and_then(|region| {
+      let offset = pos.wrapping_sub(shared_base(region));
+      region.read().unwrap().get(offset).ok()
+    });
+    window.or_else(shared).ok_or(err)
+  }
+
+  fn store(&mut self, pos: WordType, value: WordType) -> Result<(), MemoryErr> {
+    let err = match self.memory.set(pos, value) {
+      Ok(()) => return Ok(()),
+      Err(err) => err
+    };
+
+    match &self.shared {
+      Some(region) => {
+        let offset = pos.wrapping_sub(shared_base(region));
+        region.write().unwrap().set(offset, value).map_err(|_| err)
       },
-      Instruction::Multiply(into, from) => {
-        let val1 = self.registers[into as usize];
-        let val2 = self.registers[from as usize];
-        let (result, overflow) = val1.overflowing_mul(val2);
-        self.registers[into as usize] = result;
-
-        // Update the OVERFLOW flag
-        if overflow {
-          self.registers[FLAGS] = self.registers[FLAGS] | FLAG_OVERFLOW;
-        } else {
-          self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_OVERFLOW;
-        }
-        Ok(())
+      None => Err(err)
+    }
+  }
+
+  // Replaces the word at `pos` with `f` of it and returns the old word. A shared region stays locked
+  // for the whole update, so other holders never see it half done.
+  fn update<F: FnMut(WordType) -> WordType>(&mut self, pos: WordType, mut f: F) -> Result<WordType, MemoryErr> {
+    let err = match self.memory.get(pos) {
+      Ok(old) => {
+        self.memory.set(pos, f(old))?;
+        return Ok(old);
       },
-      Instruction::Divide(into, from) => {
-        let val1 = self.registers[into as usize];
-        let val2 = self.registers[from as usize];
-        let result = val1 / val2;
-        self.registers[into as usize] = result;
+      Err(err) => err
+    };
 
-        self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_OVERFLOW;
-        Ok(())
+    match &self.shared {
+      Some(region) => {
+        let offset = pos.wrapping_sub(shared_base(region));
+        let mut region = region.write().unwrap();
+        let old = match region.get(offset) {
+          Ok(old) => old,
+          Err(_) => return Err(err)
+        };
+        region.set(offset, f(old))?;
+        Ok(old)
       },
-      Instruction::Equal(reg1, reg2) => {
-        if self.registers[reg1 as usize] == self.registers[reg2 as usize] {
-          self.registers[FLAGS] = self.registers[FLAGS] | FLAG_COMPARISON;
-        } else {
-          self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_COMPARISON;
-        }
+      None => Err(err)
+    }
+  }
+
+  // The `count` words from `pos` on, each readable the way `load` would read it.
+  fn load_range(&self, pos: WordType, count: WordType) -> Result<Vec<WordType>, MemoryErr> {
+    let err = match self.memory.get_range(pos, count) {
+      Ok(words) => return Ok(words.to_vec()),
+      Err(err) => err
+    };
+
+    if pos as usize + count as usize > WORD_MAX as usize + 1 {
+      return Err(err);
+    }
+    (0..count).map(|index| self.load(pos + index).map_err(|_| err.clone())).collect()
+  }
+
+  // Writes `words` from `pos` on. Unless `store` would take every one of them nothing is written.
+  fn store_range(&mut self, pos: WordType, words: &[WordType]) -> Result<(), MemoryErr> {
+    let err = match self.memory.set_range(pos, words) {
+      Ok(()) => return Ok(()),
+      Err(err) => err
+    };
+
+    let shared = self.shared.as_ref().map(|region| (shared_base(region), region.read().unwrap().len()));
+    let writable = |pos: WordType| {
+      pos < self.memory.len() || shared.is_some_and(|(base, len)| pos.wrapping_sub(base) < len)
+    };
+    if pos as usize + words.len() > WORD_MAX as usize + 1 || !(0..words.len()).all(|index| writable(pos + index as WordType)) {
+      return Err(err);
+    }
+    for (index, word) in words.iter().enumerate() {
+      self.store(pos + index as WordType, *word)?;
+    }
+    Ok(())
+  }
+
+  fn stack_len(&self) -> WordType {
+    match self.stack_mode {
+      StackMode::Separate(_) => self.stack.len(),
+      StackMode::InMemory { size, .. } => size
+    }
+  }
+
+  fn stack_get(&self, slot: WordType) -> Result<WordType, MemoryErr> {
+    match (self.stack_mode, self.stack_address(slot)) {
+      (StackMode::Separate(_), _) => self.stack.get(slot),
+      (_, Some(pos)) => self.load(pos),
+      (_, None) => Err(MemoryErr::PointerOutOfRange(self.stack_len(), slot))
+    }
+  }
+
+  fn stack_set(&mut self, slot: WordType, value: WordType) -> Result<(), MemoryErr> {
+    match (self.stack_mode, self.stack_address(slot)) {
+      (StackMode::Separate(_), _) => self.stack.set(slot, value),
+      (_, Some(pos)) => self.store(pos, value),
+      (_, None) => Err(MemoryErr::PointerOutOfRange(self.stack_len(), slot))
+    }
+  }
+
+  // All or nothing, like `Memory::set_range`.
+  fn stack_set_range(&mut self, slot: WordType, values: &[WordType]) -> Result<(), MemoryErr> {
+    if let StackMode::Separate(_) = self.stack_mode {
+      return self.stack.set_range(slot, values);
+    }
+    let end = slot as usize + values.len();
+    if end > self.stack_len() as usize {
+      return Err(MemoryErr::PointerRangeOverflow(self.stack_len(), slot, values.len() as WordType));
+    }
+    for (slot, value) in (slot..).zip(values) {
+      self.stack_set(slot, *value)?;
+    }
+    Ok(())
+  }
+
+  fn stack_get_range(&self, slot: WordType, into: &mut [WordType]) -> Result<(), MemoryErr> {
+    for (slot, value) in (slot..).zip(into.iter_mut()) {
+      *value = self.stack_get(slot)?;
+    }
+    Ok(())
+  }
+
+  fn raise(&mut self, number: u8) -> Result<(), CPUErr> {
+    match number {
+      INT_RANDOM => {
+        let bound = self.registers[1];
+        self.registers[0] = match bound {
+          0 => self.rng.word(),
+          bound => self.rng.below(bound as u64) as WordType
+        };
         Ok(())
       },
-      Instruction::NotEqual(reg1, reg2) => {
-        if self.registers[reg1 as usize] != self.registers[reg2 as usize] {
-          self.registers[FLAGS] = self.registers[FLAGS] | FLAG_COMPARISON;
-        } else {
-          self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_COMPARISON;
-        }
+      INT_VECTORS => {
+        std::mem::swap(&mut self.registers[0], &mut self.vectors);
         Ok(())
       },
-      Instruction::GreaterThan(reg1, reg2) => {
-        if self.registers[reg1 as usize] > self.registers[reg2 as usize] {
-          self.registers[FLAGS] = self.registers[FLAGS] | FLAG_COMPARISON;
-        } else {
-          self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_COMPARISON;
-        }
+      INT_VECTORS_READ => {
+        self.registers[0] = self.vectors;
         Ok(())
       },
-      Instruction::LessThan(reg1, reg2) => {
-        if self.registers[reg1 as usize] < self.registers[reg2 as usize] {
-          self.registers[FLAGS] = self.registers[FLAGS] | FLAG_COMPARISON;
-        } else {
-          self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_COMPARISON;
-        }
+      INT_TIMER => {
+        let period = self.registers[0];
+        self.registers[0] = self.timer.period;
+        self.set_timer(period);
         Ok(())
       },
-      Instruction::Xor(reg1, reg2) => {
-        let value1 = self.registers[reg1 as usize];
-        let value2 = self.registers[reg2 as usize];
-        if (value1 > 0 && value2 > 0) || (value1 == 0 && value2 == 0) {
-          self.registers[FLAGS] = self.registers[FLAGS] | FLAG_COMPARISON;
-        } else {
-          self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_COMPARISON;
-        }
+      INT_FORK => {
+        let id = self.fork(self.registers[0], self.registers[1]);
+        self.registers[0] = id.unwrap_or(0);
+        self.set_flag(FLAG_COMPARISON, id.is_some());
         Ok(())
       },
-      Instruction::Not(reg1) => {
-        if self.registers[reg1 as usize] == 0 {
-          self.registers[FLAGS] = self.registers[FLAGS] | FLAG_COMPARISON;
-        } else {
-          self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_COMPARISON;
+      INT_JOIN => {
+        // Waiting is trying again on the next turn.
+        let id = self.registers[0];
+        if id != self.thread && self.is_running(id) {
+          self.registers[PC] = self.registers[PC].wrapping_sub(1);
         }
         Ok(())
       },
-      Instruction::Jump(reg, condition) => {
-        match condition {
-          0 => {
-            self.registers[PC] = self.registers[reg as usize] - 1;
-            Ok(())
-          },
-          1 => {
-            if (self.registers[FLAGS] & FLAG_COMPARISON) == FLAG_COMPARISON {
-              self.registers[PC] = self.registers[reg as usize] - 1;
-            }
-            Ok(())
-          },
-          2 => {
-            if (self.registers[FLAGS] & FLAG_COMPARISON) != FLAG_COMPARISON {
-              self.registers[PC] = self.registers[reg as usize] - 1;
-            }
-            Ok(())
-          },
-          3 => {
-            if (self.registers[FLAGS] & FLAG_OVERFLOW) == FLAG_OVERFLOW {
-              self.registers[PC] = self.registers[reg as usize] - 1;
-            }
-            Ok(())
-          }
-          any => Err(CPUErr::InvalidJumpCondition(any))
-        }
-      },
-      Instruction::BitShiftLeft(reg1, reg2) => {
-        let (result, overflow) = self.registers[reg1 as usize].overflowing_shl(self.registers[reg2 as usize] as u32);
-        self.registers[reg1 as usize] = result;
-
-        // Update the OVERFLOW flag
-        if overflow {
-          self.registers[FLAGS] = self.registers[FLAGS] | FLAG_OVERFLOW;
-        } else {
-          self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_OVERFLOW;
-        }
+      INT_KILL => {
+        let killed = self.kill(self.registers[0]);
+        self.set_flag(FLAG_COMPARISON, killed);
         Ok(())
       },
-      Instruction::BitShiftRight(reg1, reg2) => {
-        let (result, overflow) = self.registers[reg1 as usize].overflowing_shr(self.registers[reg2 as usize] as u32);
-        self.registers[reg1 as usize] = result;
-
-        // Update the OVERFLOW flag
-        if overflow {
-          self.registers[FLAGS] = self.registers[FLAGS] | FLAG_OVERFLOW;
+      number => {
+        // Handlers return to the instruction after this one. Like a jump, PC is left one short of
+        // the handler.
+        let next = self.registers[PC].wrapping_add(1);
+        if !self.is_claimed(number) && self.deliver(VEC_SOFTWARE + number as WordType, next).is_some() {
+          self.registers[PC] = self.registers[PC].wrapping_sub(1);
         } else {
-          self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_OVERFLOW;
+          self.interrupt = Some(number);
         }
         Ok(())
       },
-      Instruction::BitNot(reg) => {
-        self.registers[reg as usize] = !self.registers[reg as usize];
-        Ok(())
-      },
-      Instruction::BitXor(reg1, reg2) => {
-        let result = self.registers[reg1 as usize] ^ self.registers[reg2 as usize];
-        self.registers[reg1 as usize] = result;
-
-        Ok(())
-      },
-      Instruction::BitAnd(reg1, reg2) => {
-        let result = self.registers[reg1 as usize] & self.registers[reg2 as usize];
-        self.registers[reg1 as usize] = result;
-
-        Ok(())
-      },
-      Instruction::BitOr(reg1, reg2) => {
-        let result = self.registers[reg1 as usize] | self.registers[reg2 as usize];
-        self.registers[reg1 as usize] = result;
-
-        Ok(())
-      },
-      Instruction::BitNor(reg1, reg2) => {
-        let result = self.registers[reg1 as usize] | self.registers[reg2 as usize];
-        self.registers[reg1 as usize] = !result;
-
-        Ok(())
-      },
-      Instruction::LoadRelative(offset) => {
-        let position: WordType = ((self.registers[PC] as ConversionType) + (offset as ConversionType)) as WordType;
-        match self.memory.get(position) {
-          Ok(value) => {
-            self.registers[0] = value;
-            Ok(())
-          },
-          Err(err) => Err(CPUErr::MemoryErr(err))
-        }
-      },
-      Instruction::SaveRelative(offset) => {
-        let position: WordType = ((self.registers[PC] as ConversionType) + (offset as ConversionType)) as WordType;
-        match self.memory.set(position, self.registers[0]) {
-          Ok(()) => Ok(()),
-          Err(err) => Err(CPUErr::MemoryErr(err))
-        }
-      },
-      Instruction::JumpRelative(offset, condition) => {
-        let position: WordType = ((self.registers[PC] as ConversionType) + (offset as ConversionType)) as WordType;
-        match condition {
-          0 => {
-            self.registers[PC] = position;
-            Ok(())
-          },
-          1 => {
-            if (self.registers[FLAGS] & FLAG_COMPARISON) == FLAG_COMPARISON {
-              self.registers[PC] = position;
-            }
-            Ok(())
-          },
-          2 => {
-            if (self.registers[FLAGS] & FLAG_COMPARISON) != FLAG_COMPARISON {
-              self.registers[PC] = position;
-            }
-            Ok(())
-          },
-          3 => {
-            if (self.registers[FLAGS] & FLAG_OVERFLOW) == FLAG_OVERFLOW {
-              self.registers[PC] = position;
-            }
-            Ok(())
-          }
-          any => Err(CPUErr::InvalidJumpCondition(any))
-        }
-      },
-      _ => Ok(())
     }
   }
 }
 
-#[derive(Debug)]
+// Cuts an in-memory stack short where it would leave a memory of `len` words.
+fn fit_stack(mode: StackMode, len: WordType) -> StackMode {
+  match mode {
+    StackMode::InMemory { base, size, growth } => {
+      let room = match growth {
+        _ if base >= len => 0,
+        Growth::Up => len - base,
+        Growth::Down => base.saturating_add(1)
+      };
+      StackMode::InMemory { base, size: size.min(room), growth }
+    },
+    mode => mode
+  }
+}
+
+fn shared_base(region: &SharedArc<Memory>) -> WordType {
+  (0 as WordType).wrapping_sub(region.read().unwrap().len())
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum CPUErr {
   MemoryErr(MemoryErr),
   StackOverflow,
   StackUnderflow,
   InvalidJumpCondition(u8),
+  DivideByZero,
+  OutOfEnergy,
   Unreachable(String)
 }
\ No newline at end of file
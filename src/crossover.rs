@@ -0,0 +1,135 @@
+use crate::cpu::{CPU, WordType};
+use crate::memory::Memory;
+use crate::mutation::relative_target;
+use crate::rng::Rng;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Entry {
+  Start,
+  Fixed(WordType),
+  Random,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StackSize {
+  Fixed(WordType),
+  // One stack word for every `n` words of genome, but never less than one.
+  PerGenome(WordType),
+}
+
+// How a child CPU is built around a freshly recombined image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChildPolicy {
+  pub entry: Entry,
+  pub stack_size: StackSize,
+}
+
+impl Default for ChildPolicy {
+  fn default() -> Self {
+    ChildPolicy {
+      entry: Entry::Start,
+      stack_size: StackSize::Fixed(64),
+    }
+  }
+}
+
+// Recombines two parent images into a child image, drawing cut points from a seeded PRNG.
+pub struct Crossover {
+  rng: Rng,
+}
+
+impl Crossover {
+  pub fn new(seed: u64) -> Self {
+    Crossover {
+      rng: Rng::new(seed)
+    }
+  }
+
+  // `first[..cut]` followed by `second[cut..]`. The child is as long as `second`.
+  pub fn single_point(&mut self, first: &Memory, second: &Memory) -> Memory {
+    let (first, second) = (first.words(), second.words());
+    let cut = self.cut(first.len().min(second.len()));
+    image(first[..cut].iter().chain(second[cut..].iter()))
+  }
+
+  // `first` with `second[start..end]` spliced in. The child is as long as `first`.
+  pub fn two_point(&mut self, first: &Memory, second: &Memory) -> Memory {
+    let (first, second) = (first.words(), second.words());
+    let shared = first.len().min(second.len());
+    let (a, b) = (self.cut(shared), self.cut(shared));
+    let (start, end) = (a.min(b), a.max(b));
+    image(first[..start].iter().chain(second[start..end].iter()).chain(first[end..].iter()))
+  }
+
+  // Every word the parents share a position for is drawn from either one with equal odds; the rest
+  // comes from `first`.
+  pub fn uniform(&mut self, first: &Memory, second: &Memory) -> Memory {
+    let (first, second) = (first.words(), second.words());
+    let rng = &mut self.rng;
+    image(first.iter().enumerate().map(|(pos, word)| {
+      match second.get(pos) {
+        Some(other) if rng.chance(0.5) => other,
+        _ => word
+      }
+    }))
+  }
+
+  // Like `single_point`, but only cuts where no relative load, save or jump in either parent would
+  // end up separated from the word it reaches.
+  pub fn aligned(&mut self, first: &Memory, second: &Memory) -> Memory {
+    let (first, second) = (first.words(), second.words());
+    let shared = first.len().min(second.len());
+    let (first_spans, second_spans) = (spanned(first), spanned(second));
+    let cuts: Vec<usize> = (0..=shared)
+      .filter(|cut| !first_spans[*cut] && !second_spans[*cut])
+      .collect();
+    let cut = cuts[self.rng.below(cuts.len() as u64) as usize];
+    image(first[..cut].iter().chain(second[cut..].iter()))
+  }
+
+  pub fn child(&mut self, policy: &ChildPolicy, memory: Memory) -> CPU {
+    let pc = match policy.entry {
+      Entry::Start => 0,
+      Entry::Fixed(pc) => pc,
+      Entry::Random if !memory.is_empty() => self.rng.below(memory.len() as u64) as WordType,
+      Entry::Random => 0
+    };
+    let stack_size = match policy.stack_size {
+      StackSize::Fixed(size) => size,
+      StackSize::PerGenome(words) => (memory.len() / words.max(1)).max(1)
+    };
+    CPU::new(memory, pc, stack_size)
+  }
+
+  fn cut(&mut self, len: usize) -> usize {
+    self.rng.below(len as u64 + 1) as usize
+  }
+}
+
+fn image<'a, I: Iterator<Item = &'a WordType>>(words: I) -> Memory {
+  let words: Vec<WordType> = words.copied().collect();
+  Memory::from(&words[..])
+}
+
+// For every cut from 0 to `words.len()`, whether cutting there separates a relative instruction from
+// its target. Targets outside of the image are never kept together anyway, so they don't count.
+// This keeps a cut at zero valid.
+fn spanned(words: &[WordType]) -> Vec<bool> {
+  // A pair is split by every cut in `(low, high]`, so count pairs opening and closing at each cut.
+  let mut open = vec![0i64; words.len() + 2];
+  for (addr, word) in words.iter().enumerate() {
+    if let Some((target, _)) = relative_target(*word, addr) {
+      if target >= 0 && (target as usize) < words.len() {
+        let (low, high) = (addr.min(target as usize), addr.max(target as usize));
+        open[low + 1] += 1;
+        open[high + 1] -= 1;
+      }
+    }
+  }
+
+  let mut depth = 0;
+  open[..=words.len()].iter().map(|change| {
+    depth += change;
+    depth > 0
+  }).collect()
+}
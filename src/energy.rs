@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use crate::cpu::WordType;
+use crate::instruction::codes::*;
+use crate::instruction::INSTRUCTION_MASK;
+
+// Energy charged for each opcode, indexed by the `codes::*` value, plus `word` for every word a
+// block operation goes over.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostTable {
+  costs: [u64; INSTRUCTION_MASK as usize + 1],
+  word: u64,
+}
+
+impl CostTable {
+  pub fn uniform(cost: u64) -> Self {
+    CostTable {
+      costs: [cost; INSTRUCTION_MASK as usize + 1],
+      word: cost,
+    }
+  }
+
+  pub fn cost(&self, opcode: WordType) -> u64 {
+    self.costs[(opcode & INSTRUCTION_MASK) as usize]
+  }
+
+  pub fn set(&mut self, opcode: WordType, cost: u64) {
+    self.costs[(opcode & INSTRUCTION_MASK) as usize] = cost;
+  }
+
+  pub fn word_cost(&self) -> u64 {
+    self.word
+  }
+
+  pub fn set_word_cost(&mut self, cost: u64) {
+    self.word = cost;
+  }
+}
+
+// Memory traffic, multiplication and division, and calls into the host cost more than the rest.
+impl Default for CostTable {
+  fn default() -> Self {
+    let mut table = CostTable::uniform(1);
+    for opcode in &[PUSH, POP, LD, SAV, LD_REL, SAV_REL, MEM] {
+      table.set(*opcode, 2);
+    }
+    for opcode in &[PUSHS, POPS] {
+      table.set(*opcode, 8);
+    }
+    for opcode in &[MUL, DIV] {
+      table.set(*opcode, 4);
+    }
+    table.set(INT, 10);
+    table
+  }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Meter {
+  pub(crate) energy: u64,
+  pub(crate) costs: Arc<CostTable>,
+}
@@ -1,16 +1,23 @@
 use crate::cpu::WordType;
 
 const INSTRUCTION_SIZE: usize = 5;
-const INSTRUCTION_MASK: WordType = 0x1F;
-const REGISTER_OFFSET: usize = 5;
-const REGISTER_SIZE: usize = 4;
-const REGISTER_MASK: WordType = 0x0F;
+pub(crate) const INSTRUCTION_MASK: WordType = 0x1F;
+pub(crate) const REGISTER_OFFSET: usize = 5;
+pub(crate) const REGISTER_SIZE: usize = 4;
+pub(crate) const REGISTER_MASK: WordType = 0x0F;
 
 const JUMP_FLAG_OFFSET: usize = 5;
 const JUMP_FLAG_MASK: WordType = 0x00E0;
 
-const ARG_OFFSET: usize = 8;
-const ARG_MASK: WordType = 0xFF00;
+pub(crate) const ARG_OFFSET: usize = 8;
+pub(crate) const ARG_MASK: WordType = 0xFF00;
+
+// Extended opcodes pick their operation with the bits left over after two registers.
+const SUBOP_OFFSET: usize = 13;
+const SUBOP_MASK: WordType = 0xE000;
+// Or with a signed immediate in place of the registers.
+const IMMEDIATE_OFFSET: usize = 5;
+const IMMEDIATE_MASK: WordType = 0x1FE0;
 
 macro_rules! get_instruction {
   ($value:ident) => {
@@ -69,6 +76,30 @@ macro_rules! set_jump_flags {
   };
 }
 
+macro_rules! get_subop {
+  ($value:ident) => {
+    (($value & SUBOP_MASK) >> SUBOP_OFFSET)
+  };
+}
+
+macro_rules! set_subop {
+  ($value:expr, $subop:expr) => {
+    ($value | ((($subop as WordType) << SUBOP_OFFSET) & SUBOP_MASK))
+  };
+}
+
+macro_rules! get_immediate {
+  ($value:ident) => {
+    ((($value & IMMEDIATE_MASK) >> IMMEDIATE_OFFSET) as u8 as i8)
+  };
+}
+
+macro_rules! set_immediate {
+  ($value:expr, $imm:expr) => {
+    ($value | ((($imm as u8 as WordType) << IMMEDIATE_OFFSET) & IMMEDIATE_MASK))
+  };
+}
+
 macro_rules! inst {
   ($name:ident, $value:expr) => {
     pub const $name: WordType = $value;
@@ -98,7 +129,7 @@ pub mod codes {
   inst!(CMP_NOT, 17);
   inst!(JMP, 18);
   inst!(INT, 19);
-  inst!(UNUSED_2, 20);
+  inst!(EXT, 20);
   inst!(BSL, 21);
   inst!(BSR, 22);
   inst!(BNOT, 23);
@@ -109,12 +140,91 @@ pub mod codes {
   inst!(LD_REL, 28);
   inst!(JREL, 29);
   inst!(SAV_REL, 30);
-  inst!(UNUSED_4, 31);
+  inst!(MEM, 31);
+
+  pub const OPCODES: &[WordType] = &[
+    NOP, PUSH, POP, PUSHS, POPS, MOVE_RR, LD, SAV, ADD, SUB, MUL, DIV, CMP_EQ, CMP_NE, CMP_GT, CMP_LT,
+    CMP_XOR, CMP_NOT, JMP, INT, EXT, BSL, BSR, BNOT, BXOR, BAND, BOR, BNOR, LD_REL, JREL, SAV_REL, MEM,
+  ];
+
+  // Operations behind EXT.
+  pub const EXT_CMP_SGT: WordType = 0;
+  pub const EXT_CMP_SLT: WordType = 1;
+  pub const EXT_ASR: WordType = 2;
+  pub const EXT_SDIV: WordType = 3;
+  pub const EXT_CAS: WordType = 4;
+  pub const EXT_FADD: WordType = 5;
+  pub const EXT_SWAP: WordType = 6;
+  pub const EXT_RETI: WordType = 7;
+
+  // Operations behind MEM. The offset forms take a signed offset from the word after them and
+  // skip over it.
+  pub const MEM_LD_OFF: WordType = 0;
+  pub const MEM_SAV_OFF: WordType = 1;
+  // Stack access `depth` words below the top, where 0 is the last word pushed.
+  pub const MEM_PEEK: WordType = 2;
+  pub const MEM_POKE: WordType = 3;
+  // Adds a signed immediate to SP.
+  pub const MEM_ADJ_SP: WordType = 4;
+  // Block operations over the r0 words starting at the addresses in their registers.
+  pub const MEM_COPY: WordType = 5;
+  pub const MEM_FILL: WordType = 6;
+  pub const MEM_CMP: WordType = 7;
+
+  // Jump conditions. JREL only has three bits for them, so COND_NOT_OVERFLOW needs JMP.
+  pub const COND_ALWAYS: u8 = 0;
+  pub const COND_COMPARISON: u8 = 1;
+  pub const COND_NOT_COMPARISON: u8 = 2;
+  pub const COND_OVERFLOW: u8 = 3;
+  pub const COND_ZERO: u8 = 4;
+  pub const COND_NOT_ZERO: u8 = 5;
+  pub const COND_NEGATIVE: u8 = 6;
+  pub const COND_CARRY: u8 = 7;
+  pub const COND_NOT_OVERFLOW: u8 = 8;
+
+  pub fn name(opcode: WordType) -> &'static str {
+    match opcode {
+      NOP => "NOP",
+      PUSH => "PUSH",
+      POP => "POP",
+      PUSHS => "PUSHS",
+      POPS => "POPS",
+      MOVE_RR => "MOVE_RR",
+      LD => "LD",
+      SAV => "SAV",
+      ADD => "ADD",
+      SUB => "SUB",
+      MUL => "MUL",
+      DIV => "DIV",
+      CMP_EQ => "CMP_EQ",
+      CMP_NE => "CMP_NE",
+      CMP_GT => "CMP_GT",
+      CMP_LT => "CMP_LT",
+      CMP_XOR => "CMP_XOR",
+      CMP_NOT => "CMP_NOT",
+      JMP => "JMP",
+      INT => "INT",
+      BSL => "BSL",
+      BSR => "BSR",
+      BNOT => "BNOT",
+      BXOR => "BXOR",
+      BAND => "BAND",
+      BOR => "BOR",
+      BNOR => "BNOR",
+      LD_REL => "LD_REL",
+      JREL => "JREL",
+      SAV_REL => "SAV_REL",
+      EXT => "EXT",
+      MEM => "MEM",
+      // Only reachable with bits outside the opcode mask.
+      _ => "INVALID"
+    }
+  }
 }
 
 use codes::*;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Instruction {
   Nop,
   PushRegister(u8),
@@ -143,6 +253,30 @@ pub enum Instruction {
   BitAnd(u8, u8),
   BitOr(u8, u8),
   BitNor(u8, u8),
+  SignedGreaterThan(u8, u8),
+  SignedLessThan(u8, u8),
+  ArithmeticShiftRight(u8, u8),
+  SignedDivide(u8, u8),
+  // Atomic read-modify-write of the word at `registers[address]`, as (address, value). CAS stores
+  // the value if the word equals r0 and always leaves the old word in r0, FADD and SWAP leave it in
+  // the value register.
+  CompareAndSwap(u8, u8),
+  FetchAdd(u8, u8),
+  Swap(u8, u8),
+  // Pops FLAGS and PC pushed on the way into an interrupt handler.
+  ReturnFromInterrupt,
+  // (into, base) and (base, from), addressing `registers[base]` plus the offset word.
+  LoadOffset(u8, u8),
+  SaveOffset(u8, u8),
+  // (into, depth) and (depth, from).
+  Peek(u8, u8),
+  Poke(u8, u8),
+  AdjustStack(i8),
+  // (into, from), (into, value) and (left, right), each over r0 words. Copies behave as if the source
+  // was read in full before anything is written, so the ranges may overlap.
+  CopyBlock(u8, u8),
+  FillBlock(u8, u8),
+  CompareBlock(u8, u8),
   LoadRelative(i8),
   SaveRelative(i8),
   JumpRelative(i8, u8),
@@ -178,6 +312,20 @@ impl From<WordType> for Instruction {
       CMP_NOT => Instruction::Not(get_register!(value, 0)),
       JMP => Instruction::Jump(get_register!(value, 0), get_register!(value, 1)),
       INT => Instruction::Interrupt(get_unsigned_arg!(value)),
+      EXT => {
+        let (reg1, reg2) = (get_register!(value, 0), get_register!(value, 1));
+        match get_subop!(value) {
+          EXT_CMP_SGT => Instruction::SignedGreaterThan(reg1, reg2),
+          EXT_CMP_SLT => Instruction::SignedLessThan(reg1, reg2),
+          EXT_ASR => Instruction::ArithmeticShiftRight(reg1, reg2),
+          EXT_SDIV => Instruction::SignedDivide(reg1, reg2),
+          EXT_CAS => Instruction::CompareAndSwap(reg1, reg2),
+          EXT_FADD => Instruction::FetchAdd(reg1, reg2),
+          EXT_SWAP => Instruction::Swap(reg1, reg2),
+          EXT_RETI => Instruction::ReturnFromInterrupt,
+          _ => Instruction::Invalid(value)
+        }
+      },
       BSL => Instruction::BitShiftLeft(get_register!(value, 0), get_register!(value, 1)),
       BSR => Instruction::BitShiftRight(get_register!(value, 0), get_register!(value, 1)),
       BNOT => Instruction::BitNot(get_register!(value, 0)),
@@ -188,6 +336,20 @@ impl From<WordType> for Instruction {
       LD_REL => Instruction::LoadRelative(get_relative!(value)),
       JREL => Instruction::JumpRelative(get_relative!(value), get_jump_flags!(value)),
       SAV_REL => Instruction::SaveRelative(get_relative!(value)),
+      MEM => {
+        let (reg1, reg2) = (get_register!(value, 0), get_register!(value, 1));
+        match get_subop!(value) {
+          MEM_LD_OFF => Instruction::LoadOffset(reg1, reg2),
+          MEM_SAV_OFF => Instruction::SaveOffset(reg1, reg2),
+          MEM_PEEK => Instruction::Peek(reg1, reg2),
+          MEM_POKE => Instruction::Poke(reg1, reg2),
+          MEM_ADJ_SP => Instruction::AdjustStack(get_immediate!(value)),
+          MEM_COPY => Instruction::CopyBlock(reg1, reg2),
+          MEM_FILL => Instruction::FillBlock(reg1, reg2),
+          MEM_CMP => Instruction::CompareBlock(reg1, reg2),
+          _ => Instruction::Invalid(value)
+        }
+      },
       _ => Instruction::Invalid(value)
     }
   }
@@ -305,6 +467,78 @@ impl From<&Instruction> for WordType {
         1,
         *right
       ),
+      Instruction::SignedGreaterThan(left, right) => set_register!(
+        set_register!(set_subop!(EXT, EXT_CMP_SGT), 0, *left),
+        1,
+        *right
+      ),
+      Instruction::SignedLessThan(left, right) => set_register!(
+        set_register!(set_subop!(EXT, EXT_CMP_SLT), 0, *left),
+        1,
+        *right
+      ),
+      Instruction::ArithmeticShiftRight(left, right) => set_register!(
+        set_register!(set_subop!(EXT, EXT_ASR), 0, *left),
+        1,
+        *right
+      ),
+      Instruction::SignedDivide(into, from) => set_register!(
+        set_register!(set_subop!(EXT, EXT_SDIV), 0, *into),
+        1,
+        *from
+      ),
+      Instruction::CompareAndSwap(address, value) => set_register!(
+        set_register!(set_subop!(EXT, EXT_CAS), 0, *address),
+        1,
+        *value
+      ),
+      Instruction::FetchAdd(address, value) => set_register!(
+        set_register!(set_subop!(EXT, EXT_FADD), 0, *address),
+        1,
+        *value
+      ),
+      Instruction::Swap(address, value) => set_register!(
+        set_register!(set_subop!(EXT, EXT_SWAP), 0, *address),
+        1,
+        *value
+      ),
+      Instruction::ReturnFromInterrupt => set_subop!(EXT, EXT_RETI),
+      Instruction::LoadOffset(into, base) => set_register!(
+        set_register!(set_subop!(MEM, MEM_LD_OFF), 0, *into),
+        1,
+        *base
+      ),
+      Instruction::SaveOffset(base, from) => set_register!(
+        set_register!(set_subop!(MEM, MEM_SAV_OFF), 0, *base),
+        1,
+        *from
+      ),
+      Instruction::Peek(into, depth) => set_register!(
+        set_register!(set_subop!(MEM, MEM_PEEK), 0, *into),
+        1,
+        *depth
+      ),
+      Instruction::Poke(depth, from) => set_register!(
+        set_register!(set_subop!(MEM, MEM_POKE), 0, *depth),
+        1,
+        *from
+      ),
+      Instruction::AdjustStack(amount) => set_immediate!(set_subop!(MEM, MEM_ADJ_SP), *amount),
+      Instruction::CopyBlock(into, from) => set_register!(
+        set_register!(set_subop!(MEM, MEM_COPY), 0, *into),
+        1,
+        *from
+      ),
+      Instruction::FillBlock(into, value) => set_register!(
+        set_register!(set_subop!(MEM, MEM_FILL), 0, *into),
+        1,
+        *value
+      ),
+      Instruction::CompareBlock(left, right) => set_register!(
+        set_register!(set_subop!(MEM, MEM_CMP), 0, *left),
+        1,
+        *right
+      ),
       Instruction::LoadRelative(rel) => set_relative!(LD_REL, *rel),
       Instruction::JumpRelative(rel, flags) => set_jump_flags!(set_relative!(JREL, *rel), *flags),
       Instruction::SaveRelative(rel) => set_relative!(SAV_REL, *rel),
@@ -0,0 +1,518 @@
+use crate::cpu::{
+  CPUErr, ConversionType, SignedType, WordType, FLAGS, FLAG_CARRY, FLAG_COMPARISON, FLAG_NEGATIVE, FLAG_OVERFLOW,
+  FLAG_SIGNED_OVERFLOW, FLAG_ZERO, PC, STACK_POINTER,
+};
+use crate::instruction::Instruction;
+use crate::instruction::codes::{
+  COND_ALWAYS, COND_CARRY, COND_COMPARISON, COND_NEGATIVE, COND_NOT_COMPARISON, COND_NOT_OVERFLOW, COND_NOT_ZERO,
+  COND_OVERFLOW, COND_ZERO,
+};
+use crate::memory::MemoryErr;
+
+// The instruction set, shared by every machine that runs it. A machine brings its registers, its
+// memory and its stack, and decides what INT does beyond what `do_instruction` covers.
+pub(crate) trait Interpreter {
+  fn registers(&self) -> &[WordType; 16];
+  fn registers_mut(&mut self) -> &mut [WordType; 16];
+
+  // A word of code, like the offset after LDO and STO.
+  fn fetch(&self, pos: WordType) -> Result<WordType, MemoryErr>;
+  fn load(&self, pos: WordType) -> Result<WordType, MemoryErr>;
+  fn store(&mut self, pos: WordType, value: WordType) -> Result<(), MemoryErr>;
+  // Replaces the word at `pos` with `f` of it and returns the old word, atomically.
+  fn update<F: FnMut(WordType) -> WordType>(&mut self, pos: WordType, f: F) -> Result<WordType, MemoryErr>;
+  fn load_range(&self, pos: WordType, count: WordType) -> Result<Vec<WordType>, MemoryErr>;
+  // All or nothing, like `Memory::set_range`.
+  fn store_range(&mut self, pos: WordType, words: &[WordType]) -> Result<(), MemoryErr>;
+
+  fn stack_len(&self) -> WordType;
+  fn stack_get(&self, slot: WordType) -> Result<WordType, MemoryErr>;
+  fn stack_set(&mut self, slot: WordType, value: WordType) -> Result<(), MemoryErr>;
+  // All or nothing, like `store_range`.
+  fn stack_set_range(&mut self, slot: WordType, values: &[WordType]) -> Result<(), MemoryErr>;
+  fn stack_get_range(&self, slot: WordType, into: &mut [WordType]) -> Result<(), MemoryErr>;
+
+  // Services `INT number`.
+  fn raise(&mut self, number: u8) -> Result<(), CPUErr>;
+
+  // Position of the word `depth` below the top of the stack.
+  fn stack_slot(&self, depth: u8) -> Result<WordType, CPUErr> {
+    let top = self.registers()[STACK_POINTER];
+    if top > self.stack_len() {
+      return Err(CPUErr::StackOverflow);
+    }
+    top.checked_sub(depth as WordType + 1).ok_or(CPUErr::StackUnderflow)
+  }
+
+  fn set_flag(&mut self, flag: WordType, value: bool) {
+    if value {
+      self.registers_mut()[FLAGS] |= flag;
+    } else {
+      self.registers_mut()[FLAGS] &= !flag;
+    }
+  }
+
+  fn set_result_flags(&mut self, result: WordType) {
+    self.set_flag(FLAG_ZERO, result == 0);
+    self.set_flag(FLAG_NEGATIVE, (result as SignedType) < 0);
+  }
+
+  // Whether a jump with `condition` is taken, shared by JMP and JREL.
+  fn condition(&self, condition: u8) -> Result<bool, CPUErr> {
+    let flag = |flag: WordType| (self.registers()[FLAGS] & flag) == flag;
+    match condition {
+      COND_ALWAYS => Ok(true),
+      COND_COMPARISON => Ok(flag(FLAG_COMPARISON)),
+      COND_NOT_COMPARISON => Ok(!flag(FLAG_COMPARISON)),
+      COND_OVERFLOW => Ok(flag(FLAG_OVERFLOW)),
+      COND_ZERO => Ok(flag(FLAG_ZERO)),
+      COND_NOT_ZERO => Ok(!flag(FLAG_ZERO)),
+      COND_NEGATIVE => Ok(flag(FLAG_NEGATIVE)),
+      COND_CARRY => Ok(flag(FLAG_CARRY)),
+      COND_NOT_OVERFLOW => Ok(!flag(FLAG_OVERFLOW)),
+      any => Err(CPUErr::InvalidJumpCondition(any))
+    }
+  }
+
+  // `registers[base]` plus the signed offset in the word after the instruction at PC.
+  fn offset_address(&self, base: u8) -> Result<WordType, MemoryErr> {
+    let offset = self.fetch(self.registers()[PC].wrapping_add(1))?;
+    Ok(self.registers()[base as usize].wrapping_add(offset))
+  }
+
+  fn do_instruction(&mut self, instruction: Instruction) -> Result<(), CPUErr> {
+    match instruction {
+      Instruction::PushRegister(reg) => {
+        // Attempt to push the value onto the stack
+        match self.stack_set(self.registers()[STACK_POINTER], self.registers()[reg as usize]) {
+          // Valid stack position
+          Ok(()) => {
+
+            // Increment the stack pointer
+            self.registers_mut()[STACK_POINTER] += 1;
+            Ok(())
+          },
+
+          // Handle a stack overflow
+          Err(_) => Err(CPUErr::StackOverflow)
+        }
+      },
+      Instruction::PopRegister(reg) => {
+        match self.registers()[STACK_POINTER].checked_sub(1) {
+          // Valid stack position
+          Some(pos) => {
+            // Save stack position
+            self.registers_mut()[STACK_POINTER] = pos;
+
+            // Retrieve the stack value and put it on the register
+            match self.stack_get(pos) {
+              Ok(value) => {
+                self.registers_mut()[reg as usize] = value;
+                Ok(())
+              },
+              // SP was moved past the end of the stack
+              _ => Err(CPUErr::StackOverflow)
+            }
+          },
+
+          // Handle a stack underflow
+          None => Err(CPUErr::StackUnderflow)
+        }
+      },
+      Instruction::PushRegisters => {
+        let registers = *self.registers();
+        match self.stack_set_range(self.registers()[STACK_POINTER], &registers[0..FLAGS]) {
+          Ok(()) => {
+            self.registers_mut()[STACK_POINTER] += FLAGS as WordType;
+            Ok(())
+          },
+          _ => Err(CPUErr::StackOverflow)
+        }
+      },
+      Instruction::PopRegisters => {
+        match self.registers()[STACK_POINTER].checked_sub(FLAGS as WordType) {
+          Some(pos) => {
+            self.registers_mut()[STACK_POINTER] = pos;
+            let mut regs = [0; FLAGS];
+            match self.stack_get_range(pos, &mut regs) {
+              Ok(()) => {
+                self.registers_mut()[0..FLAGS].clone_from_slice(&regs);
+                Ok(())
+              },
+              _ => Err(CPUErr::StackOverflow)
+            }
+          },
+          _ => Err(CPUErr::StackUnderflow)
+        }
+      },
+      Instruction::Move(into, from) => {
+        self.registers_mut()[into as usize] = self.registers()[from as usize];
+        Ok(())
+      },
+      Instruction::Load(into, src) => {
+        let pointer: WordType = self.registers()[src as usize];
+        match self.load(pointer) {
+          Ok(value) => {
+            self.registers_mut()[into as usize] = value;
+            Ok(())
+          },
+          Err(err) => Err(CPUErr::MemoryErr(err))
+        }
+      },
+      Instruction::Save(into, from) => {
+        match self.store(self.registers()[into as usize], self.registers()[from as usize]) {
+          Ok(()) => Ok(()),
+          Err(err) => Err(CPUErr::MemoryErr(err))
+        }
+      },
+      Instruction::Add(into, from) => {
+        let val1 = self.registers()[into as usize];
+        let val2 = self.registers()[from as usize];
+        let (result, overflow) = val1.overflowing_add(val2);
+        let (_, signed_overflow) = (val1 as SignedType).overflowing_add(val2 as SignedType);
+        self.registers_mut()[into as usize] = result;
+
+        // Update the OVERFLOW flags
+        if overflow {
+          self.registers_mut()[FLAGS] |= FLAG_OVERFLOW;
+        } else {
+          self.registers_mut()[FLAGS] &= !FLAG_OVERFLOW;
+        }
+        self.set_flag(FLAG_SIGNED_OVERFLOW, signed_overflow);
+        self.set_flag(FLAG_CARRY, overflow);
+        self.set_result_flags(result);
+        Ok(())
+      },
+      Instruction::Subtract(into, from) => {
+        let val1 = self.registers()[into as usize];
+        let val2 = self.registers()[from as usize];
+        let (result, overflow) = val1.overflowing_sub(val2);
+        let (_, signed_overflow) = (val1 as SignedType).overflowing_sub(val2 as SignedType);
+        self.registers_mut()[into as usize] = result;
+
+        // Update the OVERFLOW flags
+        if overflow {
+          self.registers_mut()[FLAGS] |= FLAG_OVERFLOW;
+        } else {
+          self.registers_mut()[FLAGS] &= !FLAG_OVERFLOW;
+        }
+        self.set_flag(FLAG_SIGNED_OVERFLOW, signed_overflow);
+        self.set_flag(FLAG_CARRY, overflow);
+        self.set_result_flags(result);
+        Ok(())
+      },
+      Instruction::Multiply(into, from) => {
+        let val1 = self.registers()[into as usize];
+        let val2 = self.registers()[from as usize];
+        let (result, overflow) = val1.overflowing_mul(val2);
+        let (_, signed_overflow) = (val1 as SignedType).overflowing_mul(val2 as SignedType);
+        self.registers_mut()[into as usize] = result;
+
+        // Update the OVERFLOW flags
+        if overflow {
+          self.registers_mut()[FLAGS] |= FLAG_OVERFLOW;
+        } else {
+          self.registers_mut()[FLAGS] &= !FLAG_OVERFLOW;
+        }
+        self.set_flag(FLAG_SIGNED_OVERFLOW, signed_overflow);
+        self.set_flag(FLAG_CARRY, overflow);
+        self.set_result_flags(result);
+        Ok(())
+      },
+      Instruction::Divide(into, from) => {
+        let val1 = self.registers()[into as usize];
+        let val2 = self.registers()[from as usize];
+
+        // A division never overflows or carries, whether or not it faults.
+        self.registers_mut()[FLAGS] &= !(FLAG_OVERFLOW | FLAG_SIGNED_OVERFLOW | FLAG_CARRY);
+        match val1.checked_div(val2) {
+          Some(result) => {
+            self.registers_mut()[into as usize] = result;
+            self.set_result_flags(result);
+            Ok(())
+          },
+          None => Err(CPUErr::DivideByZero)
+        }
+      },
+      Instruction::Equal(reg1, reg2) => {
+        if self.registers()[reg1 as usize] == self.registers()[reg2 as usize] {
+          self.registers_mut()[FLAGS] |= FLAG_COMPARISON;
+        } else {
+          self.registers_mut()[FLAGS] &= !FLAG_COMPARISON;
+        }
+        Ok(())
+      },
+      Instruction::NotEqual(reg1, reg2) => {
+        if self.registers()[reg1 as usize] != self.registers()[reg2 as usize] {
+          self.registers_mut()[FLAGS] |= FLAG_COMPARISON;
+        } else {
+          self.registers_mut()[FLAGS] &= !FLAG_COMPARISON;
+        }
+        Ok(())
+      },
+      Instruction::GreaterThan(reg1, reg2) => {
+        if self.registers()[reg1 as usize] > self.registers()[reg2 as usize] {
+          self.registers_mut()[FLAGS] |= FLAG_COMPARISON;
+        } else {
+          self.registers_mut()[FLAGS] &= !FLAG_COMPARISON;
+        }
+        Ok(())
+      },
+      Instruction::LessThan(reg1, reg2) => {
+        if self.registers()[reg1 as usize] < self.registers()[reg2 as usize] {
+          self.registers_mut()[FLAGS] |= FLAG_COMPARISON;
+        } else {
+          self.registers_mut()[FLAGS] &= !FLAG_COMPARISON;
+        }
+        Ok(())
+      },
+      Instruction::Xor(reg1, reg2) => {
+        let value1 = self.registers()[reg1 as usize];
+        let value2 = self.registers()[reg2 as usize];
+        if (value1 > 0 && value2 > 0) || (value1 == 0 && value2 == 0) {
+          self.registers_mut()[FLAGS] |= FLAG_COMPARISON;
+        } else {
+          self.registers_mut()[FLAGS] &= !FLAG_COMPARISON;
+        }
+        Ok(())
+      },
+      Instruction::Not(reg1) => {
+        if self.registers()[reg1 as usize] == 0 {
+          self.registers_mut()[FLAGS] |= FLAG_COMPARISON;
+        } else {
+          self.registers_mut()[FLAGS] &= !FLAG_COMPARISON;
+        }
+        Ok(())
+      },
+      Instruction::Jump(reg, condition) => {
+        if self.condition(condition)? {
+          self.registers_mut()[PC] = self.registers()[reg as usize].wrapping_sub(1);
+        }
+        Ok(())
+      },
+      Instruction::BitShiftLeft(reg1, reg2) => {
+        let value = self.registers()[reg1 as usize];
+        let (result, overflow) = value.overflowing_shl(self.registers()[reg2 as usize] as u32);
+        self.registers_mut()[reg1 as usize] = result;
+
+        // Update the OVERFLOW flag
+        if overflow {
+          self.registers_mut()[FLAGS] |= FLAG_OVERFLOW;
+        } else {
+          self.registers_mut()[FLAGS] &= !FLAG_OVERFLOW;
+        }
+        self.set_flag(FLAG_CARRY, shifted_out(value, self.registers()[reg2 as usize] as u32 % WordType::BITS, true));
+        self.set_result_flags(result);
+        Ok(())
+      },
+      Instruction::BitShiftRight(reg1, reg2) => {
+        let value = self.registers()[reg1 as usize];
+        let (result, overflow) = value.overflowing_shr(self.registers()[reg2 as usize] as u32);
+        self.registers_mut()[reg1 as usize] = result;
+
+        // Update the OVERFLOW flag
+        if overflow {
+          self.registers_mut()[FLAGS] |= FLAG_OVERFLOW;
+        } else {
+          self.registers_mut()[FLAGS] &= !FLAG_OVERFLOW;
+        }
+        self.set_flag(FLAG_CARRY, shifted_out(value, self.registers()[reg2 as usize] as u32 % WordType::BITS, false));
+        self.set_result_flags(result);
+        Ok(())
+      },
+      Instruction::BitNot(reg) => {
+        let result = !self.registers()[reg as usize];
+        self.registers_mut()[reg as usize] = result;
+        self.set_result_flags(result);
+        Ok(())
+      },
+      Instruction::BitXor(reg1, reg2) => {
+        let result = self.registers()[reg1 as usize] ^ self.registers()[reg2 as usize];
+        self.registers_mut()[reg1 as usize] = result;
+        self.set_result_flags(result);
+        Ok(())
+      },
+      Instruction::BitAnd(reg1, reg2) => {
+        let result = self.registers()[reg1 as usize] & self.registers()[reg2 as usize];
+        self.registers_mut()[reg1 as usize] = result;
+        self.set_result_flags(result);
+        Ok(())
+      },
+      Instruction::BitOr(reg1, reg2) => {
+        let result = self.registers()[reg1 as usize] | self.registers()[reg2 as usize];
+        self.registers_mut()[reg1 as usize] = result;
+        self.set_result_flags(result);
+        Ok(())
+      },
+      Instruction::BitNor(reg1, reg2) => {
+        let result = !(self.registers()[reg1 as usize] | self.registers()[reg2 as usize]);
+        self.registers_mut()[reg1 as usize] = result;
+        self.set_result_flags(result);
+        Ok(())
+      },
+      Instruction::SignedGreaterThan(reg1, reg2) => {
+        let result = (self.registers()[reg1 as usize] as SignedType) > (self.registers()[reg2 as usize] as SignedType);
+        self.set_flag(FLAG_COMPARISON, result);
+        Ok(())
+      },
+      Instruction::SignedLessThan(reg1, reg2) => {
+        let result = (self.registers()[reg1 as usize] as SignedType) < (self.registers()[reg2 as usize] as SignedType);
+        self.set_flag(FLAG_COMPARISON, result);
+        Ok(())
+      },
+      Instruction::ArithmeticShiftRight(reg1, reg2) => {
+        // Shifting by the word size or more leaves only the sign and sets OVERFLOW. This deliberately
+        // differs from BSR and BSL, which shift by the amount modulo the word size.
+        let shift = self.registers()[reg2 as usize] as u32;
+        let value = self.registers()[reg1 as usize] as SignedType;
+        let result = (value >> shift.min(WordType::BITS - 1)) as WordType;
+        self.registers_mut()[reg1 as usize] = result;
+        self.set_flag(FLAG_OVERFLOW, shift >= WordType::BITS);
+        self.set_flag(FLAG_CARRY, shifted_out(value as WordType, shift.min(WordType::BITS - 1), false));
+        self.set_result_flags(result);
+        Ok(())
+      },
+      Instruction::SignedDivide(into, from) => {
+        let val1 = self.registers()[into as usize] as SignedType;
+        let val2 = self.registers()[from as usize] as SignedType;
+        if val2 == 0 {
+          self.registers_mut()[FLAGS] &= !(FLAG_OVERFLOW | FLAG_SIGNED_OVERFLOW | FLAG_CARRY);
+          return Err(CPUErr::DivideByZero);
+        }
+
+        // The only quotient that doesn't fit is MIN / -1, which wraps back around to MIN.
+        let (result, signed_overflow) = val1.overflowing_div(val2);
+        self.registers_mut()[into as usize] = result as WordType;
+        self.set_flag(FLAG_OVERFLOW, false);
+        self.set_flag(FLAG_SIGNED_OVERFLOW, signed_overflow);
+        self.set_flag(FLAG_CARRY, false);
+        self.set_result_flags(result as WordType);
+        Ok(())
+      },
+      Instruction::CompareAndSwap(address, value) => {
+        let (expected, new) = (self.registers()[0], self.registers()[value as usize]);
+        let old = self.update(self.registers()[address as usize], |old| if old == expected { new } else { old })
+          .map_err(CPUErr::MemoryErr)?;
+        self.registers_mut()[0] = old;
+        self.set_flag(FLAG_COMPARISON, old == expected);
+        Ok(())
+      },
+      Instruction::FetchAdd(address, value) => {
+        let amount = self.registers()[value as usize];
+        let old = self.update(self.registers()[address as usize], |old| old.wrapping_add(amount))
+          .map_err(CPUErr::MemoryErr)?;
+        self.registers_mut()[value as usize] = old;
+        Ok(())
+      },
+      Instruction::Swap(address, value) => {
+        let new = self.registers()[value as usize];
+        let old = self.update(self.registers()[address as usize], |_| new).map_err(CPUErr::MemoryErr)?;
+        self.registers_mut()[value as usize] = old;
+        Ok(())
+      },
+      Instruction::LoadOffset(into, base) => {
+        let value = self.offset_address(base).and_then(|position| self.load(position));
+        match value {
+          Ok(value) => {
+            self.registers_mut()[into as usize] = value;
+            self.registers_mut()[PC] = self.registers()[PC].wrapping_add(1);
+            Ok(())
+          },
+          Err(err) => Err(CPUErr::MemoryErr(err))
+        }
+      },
+      Instruction::SaveOffset(base, from) => {
+        let value = self.registers()[from as usize];
+        match self.offset_address(base).and_then(|position| self.store(position, value)) {
+          Ok(()) => {
+            self.registers_mut()[PC] = self.registers()[PC].wrapping_add(1);
+            Ok(())
+          },
+          Err(err) => Err(CPUErr::MemoryErr(err))
+        }
+      },
+      Instruction::Peek(into, depth) => {
+        let pos = self.stack_slot(depth)?;
+        self.registers_mut()[into as usize] = self.stack_get(pos).map_err(CPUErr::MemoryErr)?;
+        Ok(())
+      },
+      Instruction::Poke(depth, from) => {
+        let pos = self.stack_slot(depth)?;
+        self.stack_set(pos, self.registers()[from as usize]).map_err(CPUErr::MemoryErr)
+      },
+      Instruction::AdjustStack(amount) => {
+        let pos = (self.registers()[STACK_POINTER] as ConversionType) + (amount as ConversionType);
+        if pos < 0 {
+          Err(CPUErr::StackUnderflow)
+        } else if pos > self.stack_len() as ConversionType {
+          Err(CPUErr::StackOverflow)
+        } else {
+          self.registers_mut()[STACK_POINTER] = pos as WordType;
+          Ok(())
+        }
+      },
+      Instruction::CopyBlock(into, from) => {
+        let words = self.load_range(self.registers()[from as usize], self.registers()[0]).map_err(CPUErr::MemoryErr)?;
+        self.store_range(self.registers()[into as usize], &words).map_err(CPUErr::MemoryErr)
+      },
+      Instruction::FillBlock(into, value) => {
+        let words = vec![self.registers()[value as usize]; self.registers()[0] as usize];
+        self.store_range(self.registers()[into as usize], &words).map_err(CPUErr::MemoryErr)
+      },
+      Instruction::CompareBlock(left, right) => {
+        let count = self.registers()[0];
+        let left = self.load_range(self.registers()[left as usize], count).map_err(CPUErr::MemoryErr)?;
+        let right = self.load_range(self.registers()[right as usize], count).map_err(CPUErr::MemoryErr)?;
+
+        // Carry is set when the first word that differs is lower on the left, as a subtraction would.
+        let difference = left.iter().zip(right.iter()).find(|(left, right)| left != right);
+        self.set_flag(FLAG_COMPARISON, difference.is_none());
+        self.set_flag(FLAG_CARRY, matches!(difference, Some((left, right)) if left < right));
+        Ok(())
+      },
+      Instruction::LoadRelative(offset) => {
+        let position: WordType = ((self.registers()[PC] as ConversionType) + (offset as ConversionType)) as WordType;
+        match self.load(position) {
+          Ok(value) => {
+            self.registers_mut()[0] = value;
+            Ok(())
+          },
+          Err(err) => Err(CPUErr::MemoryErr(err))
+        }
+      },
+      Instruction::SaveRelative(offset) => {
+        let position: WordType = ((self.registers()[PC] as ConversionType) + (offset as ConversionType)) as WordType;
+        match self.store(position, self.registers()[0]) {
+          Ok(()) => Ok(()),
+          Err(err) => Err(CPUErr::MemoryErr(err))
+        }
+      },
+      Instruction::JumpRelative(offset, condition) => {
+        if self.condition(condition)? {
+          self.registers_mut()[PC] = ((self.registers()[PC] as ConversionType) + (offset as ConversionType)) as WordType;
+        }
+        Ok(())
+      },
+      Instruction::ReturnFromInterrupt => {
+        if self.registers()[STACK_POINTER] < 2 {
+          return Err(CPUErr::StackUnderflow);
+        }
+        self.do_instruction(Instruction::PopRegister(FLAGS as u8))?;
+        self.do_instruction(Instruction::PopRegister(PC as u8))?;
+        self.registers_mut()[PC] = self.registers()[PC].wrapping_sub(1);
+        Ok(())
+      },
+      Instruction::Interrupt(number) => self.raise(number),
+      _ => Ok(())
+    }
+  }
+}
+
+// The last bit a shift by `shift` pushes out of `value`, for the carry flag.
+fn shifted_out(value: WordType, shift: u32, left: bool) -> bool {
+  match (shift, left) {
+    (0, _) => false,
+    (shift, true) => (value >> (WordType::BITS - shift)) & 1 == 1,
+    (shift, false) => (value >> (shift - 1)) & 1 == 1
+  }
+}
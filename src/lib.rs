@@ -0,0 +1,19 @@
+#[cfg(test)]
+mod tests;
+
+pub mod machine;
+pub mod shared_arc;
+pub mod instruction;
+pub mod cpu;
+pub mod crossover;
+pub mod energy;
+mod interpreter;
+pub mod lineage;
+pub mod memory;
+pub mod mutation;
+pub mod pair;
+pub mod population;
+pub mod rng;
+pub mod species;
+pub mod threaded;
+pub mod world;
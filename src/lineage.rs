@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use crate::mutation::Mutation;
+
+// How one organism came about. Seeded organisms have no parents and are generation zero; everybody
+// else is one generation past their oldest-generation parent. The first parent is the one that
+// reproduced, a second one is the partner it crossed over with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+  pub id: u64,
+  pub parents: Vec<u64>,
+  pub generation: u64,
+  pub mutations: Vec<Mutation>,
+}
+
+// Append-only log of every organism a population ever held, dead or alive, in the order they were
+// added. Ids only ever grow, so the log is sorted by id.
+#[derive(Debug, Clone, Default)]
+pub struct Lineage {
+  records: Vec<Record>,
+}
+
+impl Lineage {
+  pub fn new() -> Self {
+    Lineage::default()
+  }
+
+  pub fn record(&mut self, record: Record) {
+    debug_assert!(self.records.last().is_none_or(|last| last.id < record.id));
+    self.records.push(record);
+  }
+
+  pub fn get(&self, id: u64) -> Option<&Record> {
+    self.records.binary_search_by_key(&id, |record| record.id)
+      .ok()
+      .map(|index| &self.records[index])
+  }
+
+  pub fn records(&self) -> &[Record] {
+    &self.records
+  }
+
+  pub fn len(&self) -> usize {
+    self.records.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.records.is_empty()
+  }
+
+  // One `parent,child,generation` line for every parent of every organism, with a header.
+  pub fn edge_list(&self) -> String {
+    let mut out = String::from("parent,child,generation\n");
+    for record in &self.records {
+      for parent in &record.parents {
+        out.push_str(&format!("{},{},{}\n", parent, record.id, record.generation));
+      }
+    }
+    out
+  }
+
+  // One Newick tree per seeded organism, each on its own line. Trees only follow the first parent,
+  // so partners of a crossover only show up in the edge list.
+  pub fn newick(&self) -> String {
+    let index: HashMap<u64, usize> = self.records.iter()
+      .enumerate()
+      .map(|(index, record)| (record.id, index))
+      .collect();
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); self.records.len()];
+    let mut roots = Vec::new();
+    for (child, record) in self.records.iter().enumerate() {
+      match record.parents.first().and_then(|parent| index.get(parent)) {
+        Some(parent) => children[*parent].push(child),
+        None => roots.push(child)
+      }
+    }
+
+    let mut out = String::new();
+    for root in roots {
+      self.write_tree(root, &children, &mut out);
+      out.push_str(";\n");
+    }
+    out
+  }
+
+  // Lineages can get far deeper than the call stack, so the tree is walked with an explicit one.
+  fn write_tree(&self, root: usize, children: &[Vec<usize>], out: &mut String) {
+    let mut stack: Vec<(usize, usize)> = vec![(root, 0)];
+    while let Some((node, next)) = stack.last().copied() {
+      let kids = &children[node];
+      if next < kids.len() {
+        out.push(if next == 0 { '(' } else { ',' });
+        stack.last_mut().unwrap().1 += 1;
+        stack.push((kids[next], 0));
+      } else {
+        if !kids.is_empty() {
+          out.push(')');
+        }
+        out.push_str(&self.records[node].id.to_string());
+        stack.pop();
+      }
+    }
+  }
+}
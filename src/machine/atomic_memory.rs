@@ -0,0 +1,80 @@
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use super::memory::{Backend, MemoryErr};
+use super::word;
+use super::word::Type as WordType;
+
+
+// Shared memory without a lock. Each word is its own atomic, so processors only ever contend on the
+// words they actually touch. Plain loads and stores are relaxed: nothing orders one word against
+// another unless a guest builds it out of `update`, which acquires and releases.
+pub struct AtomicMemory {
+  mem: Box<[word::Atomic]>,
+}
+
+pub type SharedAtomicMemory = Arc<AtomicMemory>;
+
+impl AtomicMemory {
+  pub fn new(size: WordType) -> SharedAtomicMemory {
+    Arc::new(Self::new_raw(size))
+  }
+
+  pub fn new_raw(size: WordType) -> Self {
+    AtomicMemory {
+      mem: (0..size).map(|_| word::Atomic::new(0)).collect()
+    }
+  }
+
+  pub fn get(&self, pos: WordType) -> Result<WordType, MemoryErr> {
+    match self.mem.get(pos as usize) {
+      Some(word) => Ok(word.load(Ordering::Relaxed)),
+      None => Err(MemoryErr::PointerOutOfRange(self.len(), pos))
+    }
+  }
+
+  pub fn set(&self, pos: WordType, value: WordType) -> Result<(), MemoryErr> {
+    match self.mem.get(pos as usize) {
+      Some(word) => {
+        word.store(value, Ordering::Relaxed);
+        Ok(())
+      },
+      None => Err(MemoryErr::PointerOutOfRange(self.len(), pos))
+    }
+  }
+
+  // Replaces the word at `pos` with `f` of it and returns the old word. `f` may be called again if
+  // another processor wrote the word in the meantime.
+  pub fn update<F: FnMut(WordType) -> WordType>(&self, pos: WordType, mut f: F) -> Result<WordType, MemoryErr> {
+    match self.mem.get(pos as usize) {
+      Some(word) => Ok(word.fetch_update(Ordering::AcqRel, Ordering::Acquire, |old| Some(f(old))).unwrap_or_else(|old| old)),
+      None => Err(MemoryErr::PointerOutOfRange(self.len(), pos))
+    }
+  }
+
+  pub fn len(&self) -> WordType {
+    self.mem.len() as WordType
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.mem.is_empty()
+  }
+}
+
+impl Backend for SharedAtomicMemory {
+  fn get(&self, pos: WordType) -> Result<WordType, MemoryErr> {
+    AtomicMemory::get(self, pos)
+  }
+
+  fn set(&self, pos: WordType, value: WordType) -> Result<(), MemoryErr> {
+    AtomicMemory::set(self, pos, value)
+  }
+
+  fn update<F: FnMut(WordType) -> WordType>(&self, pos: WordType, f: F) -> Result<WordType, MemoryErr> {
+    AtomicMemory::update(self, pos, f)
+  }
+
+  fn len(&self) -> WordType {
+    AtomicMemory::len(self)
+  }
+}
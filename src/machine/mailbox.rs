@@ -0,0 +1,62 @@
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use super::word;
+
+type WordType = word::Type;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Message {
+  pub from: WordType,
+  pub value: WordType,
+}
+
+// Routes messages to actors by id. Every actor registered with the same postbox can reach the others.
+#[derive(Clone, Default)]
+pub struct Postbox {
+  senders: Arc<RwLock<Vec<Sender<Message>>>>,
+}
+
+impl Postbox {
+  pub fn new() -> Self {
+    Postbox::default()
+  }
+
+  pub fn register(&self) -> Mailbox {
+    let (sender, receiver) = channel();
+    let mut senders = self.senders.write().unwrap();
+    senders.push(sender);
+    Mailbox {
+      id: (senders.len() - 1) as WordType,
+      inbox: Mutex::new(receiver),
+      outbox: self.clone(),
+    }
+  }
+
+  pub fn send(&self, to: WordType, message: Message) -> bool {
+    match self.senders.read().unwrap().get(to as usize) {
+      Some(sender) => sender.send(message).is_ok(),
+      None => false
+    }
+  }
+}
+
+pub struct Mailbox {
+  id: WordType,
+  inbox: Mutex<Receiver<Message>>,
+  outbox: Postbox,
+}
+
+impl Mailbox {
+  pub fn id(&self) -> WordType {
+    self.id
+  }
+
+  pub fn send(&self, to: WordType, value: WordType) -> bool {
+    self.outbox.send(to, Message { from: self.id, value })
+  }
+
+  pub fn receive(&self) -> Option<Message> {
+    self.inbox.lock().unwrap().try_recv().ok()
+  }
+}
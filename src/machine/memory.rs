@@ -4,6 +4,9 @@ use std::sync::RwLock;
 use super::word::Type as WordType;
 use crate::shared_arc::SharedArc;
 
+// Processors fault like CPUs, whose interpreter they share.
+pub use crate::memory::MemoryErr;
+
 
 pub struct Memory {
   mem: Box<[WordType]>,
@@ -11,6 +14,47 @@ pub struct Memory {
 
 pub type SharedMemory = SharedArc<Memory>;
 
+// The memory a processor runs in. Every call stands on its own, so each backend decides how much
+// it has to lock to serve it. `update` must be atomic with respect to every other call.
+pub trait Backend {
+  fn get(&self, pos: WordType) -> Result<WordType, MemoryErr>;
+  fn set(&self, pos: WordType, value: WordType) -> Result<(), MemoryErr>;
+  fn update<F: FnMut(WordType) -> WordType>(&self, pos: WordType, f: F) -> Result<WordType, MemoryErr>;
+  fn len(&self) -> WordType;
+
+  fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  // The word at `pos` together with the length of memory, which a processor needs to step.
+  fn fetch(&self, pos: WordType) -> (Result<WordType, MemoryErr>, WordType) {
+    (self.get(pos), self.len())
+  }
+}
+
+impl Backend for SharedMemory {
+  fn get(&self, pos: WordType) -> Result<WordType, MemoryErr> {
+    self.read().unwrap().get(pos)
+  }
+
+  fn set(&self, pos: WordType, value: WordType) -> Result<(), MemoryErr> {
+    self.write().unwrap().set(pos, value)
+  }
+
+  fn update<F: FnMut(WordType) -> WordType>(&self, pos: WordType, f: F) -> Result<WordType, MemoryErr> {
+    self.write().unwrap().update(pos, f)
+  }
+
+  fn len(&self) -> WordType {
+    self.read().unwrap().len()
+  }
+
+  fn fetch(&self, pos: WordType) -> (Result<WordType, MemoryErr>, WordType) {
+    let memory = self.read().unwrap();
+    (memory.get(pos), memory.len())
+  }
+}
+
 impl Memory {
   pub fn new(size: WordType) -> SharedMemory {
     let mem = Self::new_raw(size);
@@ -32,8 +76,9 @@ impl Memory {
   }
 
   pub fn get_range(&self, pos: WordType, count: WordType) -> Result<&[WordType], MemoryErr> {
-    if (pos + count) <= self.len() {
-      Ok(&self.mem[(pos as usize)..(count as usize)])
+    let end = pos as usize + count as usize;
+    if end <= self.mem.len() {
+      Ok(&self.mem[(pos as usize)..end])
     } else {
       Err(MemoryErr::PointerRangeOverflow(self.len() as WordType, pos, count))
     }
@@ -48,12 +93,21 @@ impl Memory {
     }
   }
 
+  // Replaces the word at `pos` with `f` of it and returns the old word. Callers holding the write
+  // lock of a `SharedMemory` get the whole read-modify-write atomically.
+  pub fn update<F: FnOnce(WordType) -> WordType>(&mut self, pos: WordType, f: F) -> Result<WordType, MemoryErr> {
+    let old = self.get(pos)?;
+    self.mem[pos as usize] = f(old);
+    Ok(old)
+  }
+
   pub fn set_range(&mut self, pos: WordType, range: &[WordType]) -> Result<(), MemoryErr> {
-    if (pos as usize + range.len()) <= self.mem.len() {
-      self.mem[(pos as usize)..range.len()].clone_from_slice(range);
+    let end = pos as usize + range.len();
+    if end <= self.mem.len() {
+      self.mem[(pos as usize)..end].clone_from_slice(range);
       Ok(())
     } else {
-      Err(MemoryErr::PointerRangeOverflow(self.len() as WordType, pos, pos + (range.len() as WordType)))
+      Err(MemoryErr::PointerRangeOverflow(self.len() as WordType, pos, pos.wrapping_add(range.len() as WordType)))
     }
   }
 
@@ -61,6 +115,10 @@ impl Memory {
     self.mem.len() as WordType
   }
 
+  pub fn is_empty(&self) -> bool {
+    self.mem.is_empty()
+  }
+
   #[cfg(test)]
   pub fn raw(&self) -> &[WordType] {
     &self.mem
@@ -70,10 +128,4 @@ impl Memory {
   pub fn raw_mut(&mut self) -> &mut [WordType] {
     &mut self.mem
   }
-}
-
-#[derive(Debug, PartialEq)]
-pub enum MemoryErr {
-  PointerOutOfRange(WordType, WordType),
-  PointerRangeOverflow(WordType, WordType, WordType)
 }
\ No newline at end of file
@@ -1,3 +1,6 @@
+pub mod atomic_memory;
+pub mod mailbox;
 pub mod memory;
 pub mod processor;
+pub mod scheduler;
 pub mod word;
\ No newline at end of file
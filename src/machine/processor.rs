@@ -1,383 +1,244 @@
 use crate::shared_arc::SharedArc;
-use std::ops::Deref;
+use std::sync::{Arc, RwLock};
 
 use super::memory::{
+  Backend,
   SharedMemory,
   Memory,
   MemoryErr,
 };
 
+use super::mailbox::{Mailbox, Postbox};
 use super::word;
+use crate::cpu::{CPUErr, FLAG_COMPARISON, PC};
+use crate::instruction::Instruction;
+use crate::interpreter::Interpreter;
 
 type WordType = word::Type;
 
-const FLAGS: usize = 13;
-const PC: usize = 14;
-const STACK_POINTER: usize = 15;
+// Send r1 to the actor whose id is in r0. FLAG_COMPARISON is set if the message was accepted.
+pub const INT_SEND: u8 = 1;
+// Wait for a message, then place its value in r0 and the sender's id in r1.
+pub const INT_RECEIVE: u8 = 2;
+// Like INT_RECEIVE without waiting. FLAG_COMPARISON is set if a message was received.
+pub const INT_POLL: u8 = 3;
 
-const FLAG_OVERFLOW: WordType = 0x0001;
-const FLAG_COMPARISON: WordType = 0x0002;
+// Processors run the CPU's interpreter and fault the same way.
+pub type ProcessorError = CPUErr;
 
-
-#[derive(Debug)]
-pub enum ProcessorError {
-  MemoryErr(MemoryErr),
-  StackOverflow,
-  StackUnderflow,
-  InvalidJumpCondition(u8),
-  Unreachable(String)
-}
-
-struct Processor {
+// Runs in any `Backend`. The `RwLock` backed `SharedMemory` is the default.
+pub struct Processor<M: Backend = SharedMemory> {
   registers: [WordType; 16],
-  mem: SharedMemory,
+  mem: M,
   stack: Memory,
   running: bool,
+  waiting: bool,
+  interrupt: Option<u8>,
+  cycles: u64,
 }
 
-impl Processor {
-  pub fn new(mem: SharedMemory, stack_size: WordType, program_counter: WordType) -> Self {
+impl<M: Backend> Processor<M> {
+  pub fn new(mem: M, stack_size: WordType, program_counter: WordType) -> Self {
     let mut processor = Processor {
       registers: [0; 16],
       mem,
       stack: Memory::new_raw(stack_size),
       running: false,
+      waiting: false,
+      interrupt: None,
+      cycles: 0,
     };
     processor.registers[PC] = program_counter;
     processor
   }
 
+  pub fn registers(&self) -> &[WordType; 16] {
+    &self.registers
+  }
+
+  pub fn is_running(&self) -> bool {
+    self.running
+  }
+
+  pub fn is_waiting(&self) -> bool {
+    self.waiting
+  }
+
+  pub fn cycles(&self) -> u64 {
+    self.cycles
+  }
+
   pub fn step(&mut self) -> Result<(), ProcessorError> {
-    let op = self.mem.deref().read().unwrap().get(self.registers[PC]);
+    self.cycles += 1;
+    let (op, len) = self.mem.fetch(self.registers[PC]);
     let res = match op {
-      Ok(instruction) => self.do_instruction(instruction),
+      Ok(instruction) => self.do_instruction(Instruction::from(instruction)),
       Err(err) => {
-        match err {
-          any => {
-            self.registers[PC] = word::MAX;
-            Err(ProcessorError::MemoryErr(any))
-          }
-        }
+        self.registers[PC] = word::MAX;
+        Err(ProcessorError::MemoryErr(err))
       }
     };
 
-    self.registers[PC] = self.registers[PC].wrapping_add(1) % self.mem.deref().read().unwrap().len();
+    // The fetch has already failed in an empty memory, which leaves PC at 0 like the CPU does.
+    self.registers[PC] = self.registers[PC].wrapping_add(1).checked_rem(len).unwrap_or(0);
     res
   }
+}
+
+impl<M: Backend> Interpreter for Processor<M> {
+  fn registers(&self) -> &[WordType; 16] {
+    &self.registers
+  }
+
+  fn registers_mut(&mut self) -> &mut [WordType; 16] {
+    &mut self.registers
+  }
+
+  fn fetch(&self, pos: WordType) -> Result<WordType, MemoryErr> {
+    self.mem.get(pos)
+  }
+
+  fn load(&self, pos: WordType) -> Result<WordType, MemoryErr> {
+    self.mem.get(pos)
+  }
+
+  fn store(&mut self, pos: WordType, value: WordType) -> Result<(), MemoryErr> {
+    self.mem.set(pos, value)
+  }
+
+  fn update<F: FnMut(WordType) -> WordType>(&mut self, pos: WordType, f: F) -> Result<WordType, MemoryErr> {
+    self.mem.update(pos, f)
+  }
+
+  // Word by word, so other processors may write in between.
+  fn load_range(&self, pos: WordType, count: WordType) -> Result<Vec<WordType>, MemoryErr> {
+    if pos as usize + count as usize > self.mem.len() as usize {
+      return Err(MemoryErr::PointerRangeOverflow(self.mem.len(), pos, count));
+    }
+    (0..count).map(|index| self.mem.get(pos + index)).collect()
+  }
+
+  fn store_range(&mut self, pos: WordType, words: &[WordType]) -> Result<(), MemoryErr> {
+    if pos as usize + words.len() > self.mem.len() as usize {
+      return Err(MemoryErr::PointerRangeOverflow(self.mem.len(), pos, pos.wrapping_add(words.len() as WordType)));
+    }
+    for (pos, word) in (pos..).zip(words) {
+      self.mem.set(pos, *word)?;
+    }
+    Ok(())
+  }
 
-  fn do_instruction(&mut self, instruction: WordType) -> Result<(), ProcessorError> {
+  fn stack_len(&self) -> WordType {
+    self.stack.len()
+  }
+
+  fn stack_get(&self, slot: WordType) -> Result<WordType, MemoryErr> {
+    self.stack.get(slot)
+  }
+
+  fn stack_set(&mut self, slot: WordType, value: WordType) -> Result<(), MemoryErr> {
+    self.stack.set(slot, value)
+  }
+
+  fn stack_set_range(&mut self, slot: WordType, values: &[WordType]) -> Result<(), MemoryErr> {
+    self.stack.set_range(slot, values)
+  }
+
+  fn stack_get_range(&self, slot: WordType, into: &mut [WordType]) -> Result<(), MemoryErr> {
+    into.copy_from_slice(self.stack.get_range(slot, into.len() as WordType)?);
     Ok(())
-    // match Instruction::from(instruction) {
-    //   Instruction::PushRegister(reg) => {
-    //     // Attempt to push the value onto the stack
-    //     match self.stack.set(self.registers[STACK_POINTER], self.registers[reg as usize]) {
-    //       // Valid stack position
-    //       Ok(()) => {
-
-    //         // Increment the stack pointer
-    //         self.registers[STACK_POINTER] += 1;
-    //         Ok(())
-    //       },
-
-    //       // Handle a stack overflow
-    //       Err(_) => Err(ProcessorError::StackOverflow)
-    //     }
-    //   },
-    //   Instruction::PopRegister(reg) => {
-    //     match self.registers[STACK_POINTER].checked_sub(1) {
-    //       // Valid stack position
-    //       Some(pos) => {
-    //         // Save stack position
-    //         self.registers[STACK_POINTER] = pos;
-
-    //         // Retrieve the stack value and put it on the register
-    //         match self.stack.get(pos) {
-    //           Ok(value) => {
-    //             self.registers[reg as usize] = value;
-    //             Ok(())
-    //           },
-    //           _ => Err(ProcessorError::Unreachable(String::from("This should never happen because we are already checking that we are within the stack boundaries")))
-    //         }
-    //       },
-
-    //       // Handle a stack underflow
-    //       None => Err(ProcessorError::StackUnderflow)
-    //     }
-    //   },
-    //   Instruction::PushRegisters => {
-    //     match self.stack.set_range(self.registers[STACK_POINTER], &self.registers[0..FLAGS]) {
-    //       Ok(()) => {
-    //         self.registers[STACK_POINTER] += FLAGS as WordType;
-    //         Ok(())
-    //       },
-    //       _ => Err(ProcessorError::StackOverflow)
-    //     }
-    //   },
-    //   Instruction::PopRegisters => {
-    //     match self.registers[STACK_POINTER].checked_sub(FLAGS as WordType) {
-    //       Some(pos) => {
-    //         self.registers[STACK_POINTER] = pos;
-    //         match self.stack.get_range(pos, FLAGS as WordType) {
-    //           Ok(regs) => {
-    //             &self.registers[0..FLAGS].clone_from_slice(regs);
-    //             Ok(())
-    //           },
-    //           _ => Err(ProcessorError::Unreachable(String::from("This should never happen because we are already checking that we are within the stack boundaries")))
-    //         }
-    //       },
-    //       _ => Err(ProcessorError::StackUnderflow)
-    //     }
-    //   },
-    //   Instruction::Move(into, from) => {
-    //     self.registers[into as usize] = self.registers[from as usize];
-    //     Ok(())
-    //   },
-    //   Instruction::Load(into, src) => {
-    //     let pointer: WordType = self.registers[src as usize];
-    //     match self.memory.get(pointer) {
-    //       Ok(value) => {
-    //         self.registers[into as usize] = value;
-    //         Ok(())
-    //       },
-    //       Err(err) => Err(ProcessorError::MemoryErr(err))
-    //     }
-    //   },
-    //   Instruction::Save(into, from) => {
-    //     match self.memory.set(self.registers[into as usize], self.registers[from as usize]) {
-    //       Ok(()) => Ok(()),
-    //       Err(err) => Err(ProcessorError::MemoryErr(err))
-    //     }
-    //   },
-    //   Instruction::Add(into, from) => {
-    //     let val1 = self.registers[into as usize];
-    //     let val2 = self.registers[from as usize];
-    //     let (result, overflow) = val1.overflowing_add(val2);
-    //     self.registers[into as usize] = result;
-
-    //     // Update the OVERFLOW flag
-    //     if overflow {
-    //       self.registers[FLAGS] = self.registers[FLAGS] | FLAG_OVERFLOW;
-    //     } else {
-    //       self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_OVERFLOW;
-    //     }
-    //     Ok(())
-    //   },
-    //   Instruction::Subtract(into, from) => {
-    //     let val1 = self.registers[into as usize];
-    //     let val2 = self.registers[from as usize];
-    //     let (result, overflow) = val1.overflowing_sub(val2);
-    //     self.registers[into as usize] = result;
-
-    //     // Update the OVERFLOW flag
-    //     if overflow {
-    //       self.registers[FLAGS] = self.registers[FLAGS] | FLAG_OVERFLOW;
-    //     } else {
-    //       self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_OVERFLOW;
-    //     }
-    //     Ok(())
-    //   },
-    //   Instruction::Multiply(into, from) => {
-    //     let val1 = self.registers[into as usize];
-    //     let val2 = self.registers[from as usize];
-    //     let (result, overflow) = val1.overflowing_mul(val2);
-    //     self.registers[into as usize] = result;
-
-    //     // Update the OVERFLOW flag
-    //     if overflow {
-    //       self.registers[FLAGS] = self.registers[FLAGS] | FLAG_OVERFLOW;
-    //     } else {
-    //       self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_OVERFLOW;
-    //     }
-    //     Ok(())
-    //   },
-    //   Instruction::Divide(into, from) => {
-    //     let val1 = self.registers[into as usize];
-    //     let val2 = self.registers[from as usize];
-    //     let result = val1 / val2;
-    //     self.registers[into as usize] = result;
-
-    //     self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_OVERFLOW;
-    //     Ok(())
-    //   },
-    //   Instruction::Equal(reg1, reg2) => {
-    //     if self.registers[reg1 as usize] == self.registers[reg2 as usize] {
-    //       self.registers[FLAGS] = self.registers[FLAGS] | FLAG_COMPARISON;
-    //     } else {
-    //       self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_COMPARISON;
-    //     }
-    //     Ok(())
-    //   },
-    //   Instruction::NotEqual(reg1, reg2) => {
-    //     if self.registers[reg1 as usize] != self.registers[reg2 as usize] {
-    //       self.registers[FLAGS] = self.registers[FLAGS] | FLAG_COMPARISON;
-    //     } else {
-    //       self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_COMPARISON;
-    //     }
-    //     Ok(())
-    //   },
-    //   Instruction::GreaterThan(reg1, reg2) => {
-    //     if self.registers[reg1 as usize] > self.registers[reg2 as usize] {
-    //       self.registers[FLAGS] = self.registers[FLAGS] | FLAG_COMPARISON;
-    //     } else {
-    //       self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_COMPARISON;
-    //     }
-    //     Ok(())
-    //   },
-    //   Instruction::LessThan(reg1, reg2) => {
-    //     if self.registers[reg1 as usize] < self.registers[reg2 as usize] {
-    //       self.registers[FLAGS] = self.registers[FLAGS] | FLAG_COMPARISON;
-    //     } else {
-    //       self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_COMPARISON;
-    //     }
-    //     Ok(())
-    //   },
-    //   Instruction::Xor(reg1, reg2) => {
-    //     let value1 = self.registers[reg1 as usize];
-    //     let value2 = self.registers[reg2 as usize];
-    //     if (value1 > 0 && value2 > 0) || (value1 == 0 && value2 == 0) {
-    //       self.registers[FLAGS] = self.registers[FLAGS] | FLAG_COMPARISON;
-    //     } else {
-    //       self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_COMPARISON;
-    //     }
-    //     Ok(())
-    //   },
-    //   Instruction::Not(reg1) => {
-    //     if self.registers[reg1 as usize] == 0 {
-    //       self.registers[FLAGS] = self.registers[FLAGS] | FLAG_COMPARISON;
-    //     } else {
-    //       self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_COMPARISON;
-    //     }
-    //     Ok(())
-    //   },
-    //   Instruction::Jump(reg, condition) => {
-    //     match condition {
-    //       0 => {
-    //         self.registers[PC] = self.registers[reg as usize] - 1;
-    //         Ok(())
-    //       },
-    //       1 => {
-    //         if (self.registers[FLAGS] & FLAG_COMPARISON) == FLAG_COMPARISON {
-    //           self.registers[PC] = self.registers[reg as usize] - 1;
-    //         }
-    //         Ok(())
-    //       },
-    //       2 => {
-    //         if (self.registers[FLAGS] & FLAG_COMPARISON) != FLAG_COMPARISON {
-    //           self.registers[PC] = self.registers[reg as usize] - 1;
-    //         }
-    //         Ok(())
-    //       },
-    //       3 => {
-    //         if (self.registers[FLAGS] & FLAG_OVERFLOW) == FLAG_OVERFLOW {
-    //           self.registers[PC] = self.registers[reg as usize] - 1;
-    //         }
-    //         Ok(())
-    //       }
-    //       any => Err(ProcessorError::InvalidJumpCondition(any))
-    //     }
-    //   },
-    //   Instruction::BitShiftLeft(reg1, reg2) => {
-    //     let (result, overflow) = self.registers[reg1 as usize].overflowing_shl(self.registers[reg2 as usize] as u32);
-    //     self.registers[reg1 as usize] = result;
-
-    //     // Update the OVERFLOW flag
-    //     if overflow {
-    //       self.registers[FLAGS] = self.registers[FLAGS] | FLAG_OVERFLOW;
-    //     } else {
-    //       self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_OVERFLOW;
-    //     }
-    //     Ok(())
-    //   },
-    //   Instruction::BitShiftRight(reg1, reg2) => {
-    //     let (result, overflow) = self.registers[reg1 as usize].overflowing_shr(self.registers[reg2 as usize] as u32);
-    //     self.registers[reg1 as usize] = result;
-
-    //     // Update the OVERFLOW flag
-    //     if overflow {
-    //       self.registers[FLAGS] = self.registers[FLAGS] | FLAG_OVERFLOW;
-    //     } else {
-    //       self.registers[FLAGS] = self.registers[FLAGS] & !FLAG_OVERFLOW;
-    //     }
-    //     Ok(())
-    //   },
-    //   Instruction::BitNot(reg) => {
-    //     self.registers[reg as usize] = !self.registers[reg as usize];
-    //     Ok(())
-    //   },
-    //   Instruction::BitXor(reg1, reg2) => {
-    //     let result = self.registers[reg1 as usize] ^ self.registers[reg2 as usize];
-    //     self.registers[reg1 as usize] = result;
-
-    //     Ok(())
-    //   },
-    //   Instruction::BitAnd(reg1, reg2) => {
-    //     let result = self.registers[reg1 as usize] & self.registers[reg2 as usize];
-    //     self.registers[reg1 as usize] = result;
-
-    //     Ok(())
-    //   },
-    //   Instruction::BitOr(reg1, reg2) => {
-    //     let result = self.registers[reg1 as usize] | self.registers[reg2 as usize];
-    //     self.registers[reg1 as usize] = result;
-
-    //     Ok(())
-    //   },
-    //   Instruction::BitNor(reg1, reg2) => {
-    //     let result = self.registers[reg1 as usize] | self.registers[reg2 as usize];
-    //     self.registers[reg1 as usize] = !result;
-
-    //     Ok(())
-    //   },
-    //   Instruction::LoadRelative(offset) => {
-    //     let position: WordType = ((self.registers[PC] as word::ConversionType) + (offset as word::ConversionType)) as WordType;
-    //     match self.memory.get(position) {
-    //       Ok(value) => {
-    //         self.registers[0] = value;
-    //         Ok(())
-    //       },
-    //       Err(err) => Err(ProcessorError::MemoryErr(err))
-    //     }
-    //   },
-    //   Instruction::SaveRelative(offset) => {
-    //     let position: WordType = ((self.registers[PC] as word::ConversionType) + (offset as word::ConversionType)) as WordType;
-    //     match self.memory.set(position, self.registers[0]) {
-    //       Ok(()) => Ok(()),
-    //       Err(err) => Err(ProcessorError::MemoryErr(err))
-    //     }
-    //   },
-    //   Instruction::JumpRelative(offset, condition) => {
-    //     let position: WordType = ((self.registers[PC] as word::ConversionType) + (offset as word::ConversionType)) as WordType;
-    //     match condition {
-    //       0 => {
-    //         self.registers[PC] = position;
-    //         Ok(())
-    //       },
-    //       1 => {
-    //         if (self.registers[FLAGS] & FLAG_COMPARISON) == FLAG_COMPARISON {
-    //           self.registers[PC] = position;
-    //         }
-    //         Ok(())
-    //       },
-    //       2 => {
-    //         if (self.registers[FLAGS] & FLAG_COMPARISON) != FLAG_COMPARISON {
-    //           self.registers[PC] = position;
-    //         }
-    //         Ok(())
-    //       },
-    //       3 => {
-    //         if (self.registers[FLAGS] & FLAG_OVERFLOW) == FLAG_OVERFLOW {
-    //           self.registers[PC] = position;
-    //         }
-    //         Ok(())
-    //       }
-    //       any => Err(ProcessorError::InvalidJumpCondition(any))
-    //     }
-    //   },
-    //   _ => Ok(())
-    // }
   }
+
+  // Every interrupt goes to the actor running the processor.
+  fn raise(&mut self, number: u8) -> Result<(), ProcessorError> {
+    self.interrupt = Some(number);
+    Ok(())
+  }
+}
+
+pub struct ProcessorActor<M: Backend = SharedMemory> {
+  processor: SharedArc<Processor<M>>,
+  mailbox: Mailbox,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Slice {
+  pub sent: bool,
+  pub waiting: bool,
 }
 
-pub struct ProcessorActor {
-  processor: SharedArc<Processor>,
+impl<M: Backend> ProcessorActor<M> {
+  pub fn new(processor: Processor<M>, postbox: &Postbox) -> Self {
+    ProcessorActor {
+      processor: Arc::new(RwLock::new(processor)),
+      mailbox: postbox.register(),
+    }
+  }
+
+  pub fn processor(&self) -> &SharedArc<Processor<M>> {
+    &self.processor
+  }
+
+  pub fn mailbox(&self) -> &Mailbox {
+    &self.mailbox
+  }
+
+  // Runs up to `quantum` instructions, returning early if the processor starts waiting on an empty
+  // inbox. A fault stops the processor until it is restarted.
+  pub fn run(&self, quantum: u64) -> Result<Slice, ProcessorError> {
+    let mut processor = self.processor.write().unwrap();
+    let mut slice = Slice::default();
+    processor.running = true;
+    if processor.waiting && !self.deliver(&mut processor) {
+      slice.waiting = true;
+      return Ok(slice);
+    }
+
+    for _ in 0..quantum {
+      if let Err(err) = processor.step() {
+        processor.running = false;
+        return Err(err);
+      }
+
+      match processor.interrupt.take() {
+        Some(INT_SEND) => {
+          let sent = self.mailbox.send(processor.registers[0], processor.registers[1]);
+          processor.set_flag(FLAG_COMPARISON, sent);
+          slice.sent |= sent;
+        },
+        Some(INT_RECEIVE) => {
+          let received = self.deliver(&mut processor);
+          if !received {
+            processor.waiting = true;
+            slice.waiting = true;
+            return Ok(slice);
+          }
+        },
+        Some(INT_POLL) => {
+          let received = self.deliver(&mut processor);
+          processor.set_flag(FLAG_COMPARISON, received);
+        },
+        _ => ()
+      }
+    }
+    Ok(slice)
+  }
+
+  // Returns true once the processor is no longer waiting for a message.
+  pub fn try_wake(&self) -> bool {
+    let mut processor = self.processor.write().unwrap();
+    !processor.waiting || self.deliver(&mut processor)
+  }
+
+  fn deliver(&self, processor: &mut Processor<M>) -> bool {
+    match self.mailbox.receive() {
+      Some(message) => {
+        processor.registers[0] = message.value;
+        processor.registers[1] = message.from;
+        processor.waiting = false;
+        true
+      },
+      None => false
+    }
+  }
 }
\ No newline at end of file
@@ -0,0 +1,173 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use super::mailbox::Postbox;
+use super::memory::{Backend, SharedMemory};
+use super::processor::{
+  Processor,
+  ProcessorActor,
+  ProcessorError,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExitReason {
+  Fault(ProcessorError),
+  CycleLimit,
+  Waiting,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+  pub id: usize,
+  pub cycles: u64,
+  pub exit: ExitReason,
+}
+
+// Owns a population of processors and time-slices them over a fixed pool of worker threads.
+pub struct Scheduler<M: Backend = SharedMemory> {
+  workers: usize,
+  quantum: u64,
+  actors: Vec<ProcessorActor<M>>,
+  faults: Vec<Option<ProcessorError>>,
+  postbox: Postbox,
+}
+
+impl<M: Backend + Send + Sync> Scheduler<M> {
+  pub fn new(workers: usize, quantum: u64) -> Self {
+    Scheduler {
+      workers: workers.max(1),
+      quantum: quantum.max(1),
+      actors: Vec::new(),
+      faults: Vec::new(),
+      postbox: Postbox::new(),
+    }
+  }
+
+  pub fn add(&mut self, processor: Processor<M>) -> usize {
+    self.actors.push(ProcessorActor::new(processor, &self.postbox));
+    self.faults.push(None);
+    self.actors.len() - 1
+  }
+
+  pub fn actor(&self, id: usize) -> Option<&ProcessorActor<M>> {
+    self.actors.get(id)
+  }
+
+  pub fn postbox(&self) -> &Postbox {
+    &self.postbox
+  }
+
+  pub fn len(&self) -> usize {
+    self.actors.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.actors.is_empty()
+  }
+
+  // Runs every processor that has not faulted until it faults, has executed `max_cycles`
+  // instructions in total, or is left waiting for a message nobody is going to send.
+  pub fn run(&mut self, max_cycles: u64) -> Vec<Report> {
+    let queue: Mutex<VecDeque<usize>> = Mutex::new(
+      (0..self.actors.len()).filter(|id| self.faults[*id].is_none()).collect()
+    );
+    // Processors queued or currently running. Parked processors are waiting on an empty inbox and
+    // only come back once somebody sends a message.
+    let remaining = AtomicUsize::new(queue.lock().unwrap().len());
+    // Idle workers sleep on this until something is queued or nothing is left.
+    let ready = Condvar::new();
+    let parked: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+    let faults: Mutex<Vec<(usize, ProcessorError)>> = Mutex::new(Vec::new());
+
+    let push = |id: usize| {
+      queue.lock().unwrap().push_back(id);
+      ready.notify_one();
+    };
+    let finish = || {
+      // Notified under the queue lock, so a worker can't check `remaining` and then miss this.
+      if remaining.fetch_sub(1, Ordering::AcqRel) == 1 {
+        let _queue = queue.lock().unwrap();
+        ready.notify_all();
+      }
+    };
+
+    thread::scope(|scope| {
+      for _ in 0..self.workers {
+        scope.spawn(|| {
+          loop {
+            let id = {
+              let mut queue = queue.lock().unwrap();
+              loop {
+                if let Some(id) = queue.pop_front() {
+                  break Some(id);
+                }
+                if remaining.load(Ordering::Acquire) == 0 {
+                  break None;
+                }
+                queue = ready.wait(queue).unwrap();
+              }
+            };
+            let id = match id {
+              Some(id) => id,
+              None => break
+            };
+
+            let actor = &self.actors[id];
+            let cycles = actor.processor().read().unwrap().cycles();
+            let res = actor.run(self.quantum.min(max_cycles.saturating_sub(cycles)));
+
+            if let Ok(slice) = &res {
+              if slice.sent {
+                let mut parked = parked.lock().unwrap();
+                for woken in parked.drain(..) {
+                  remaining.fetch_add(1, Ordering::AcqRel);
+                  push(woken);
+                }
+              }
+            }
+
+            match res {
+              Err(err) => {
+                faults.lock().unwrap().push((id, err));
+                finish();
+              },
+              Ok(_) if actor.processor().read().unwrap().cycles() >= max_cycles => finish(),
+              Ok(slice) if slice.waiting => {
+                // Checked under the parked lock so a message sent in the meantime is not missed.
+                let mut parked = parked.lock().unwrap();
+                if actor.try_wake() {
+                  push(id);
+                } else {
+                  parked.push(id);
+                  finish();
+                }
+              },
+              Ok(_) => push(id)
+            }
+          }
+        });
+      }
+    });
+
+    for (id, err) in faults.into_inner().unwrap() {
+      self.faults[id] = Some(err);
+    }
+    self.reports()
+  }
+
+  pub fn reports(&self) -> Vec<Report> {
+    self.actors.iter().zip(self.faults.iter()).enumerate().map(|(id, (actor, fault))| {
+      Report {
+        id,
+        cycles: actor.processor().read().unwrap().cycles(),
+        exit: match fault {
+          Some(err) => ExitReason::Fault(err.clone()),
+          None if actor.processor().read().unwrap().is_waiting() => ExitReason::Waiting,
+          None => ExitReason::CycleLimit
+        }
+      }
+    }).collect()
+  }
+}
@@ -1,4 +1,6 @@
 pub type Type = u16;
 pub use std::u16::*;
 
-pub type ConversionType = u32;
\ No newline at end of file
+pub type ConversionType = u32;
+
+pub type Atomic = std::sync::atomic::AtomicU16;
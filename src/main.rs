@@ -1,11 +1,68 @@
-#[cfg(test)]
-mod tests;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process;
 
-mod machine;
-mod shared_arc;
-mod instruction;
-mod cpu;
-mod memory;
+use vm::world::{parse_program, Stats, World, WorldConfig};
 
 fn main() {
+  let args: Vec<String> = env::args().collect();
+  if args.len() != 2 {
+    eprintln!("usage: symbios <config>");
+    process::exit(2);
+  }
+
+  if let Err(err) = run(Path::new(&args[1])) {
+    eprintln!("symbios: {}", err);
+    process::exit(1);
+  }
+}
+
+fn run(path: &Path) -> Result<(), String> {
+  let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+  let config = WorldConfig::parse(&text).map_err(|err| format!("{}: {:?}", path.display(), err))?;
+
+  // Program paths are relative to the config file.
+  let base = path.parent().unwrap_or_else(|| Path::new(""));
+  let mut programs = Vec::new();
+  for program in &config.programs {
+    let program = base.join(program);
+    let text = fs::read_to_string(&program).map_err(|err| format!("{}: {}", program.display(), err))?;
+    programs.push(parse_program(&text).map_err(|err| format!("{}: {:?}", program.display(), err))?);
+  }
+
+  let mut out: Box<dyn Write> = match &config.stats {
+    Some(stats) => {
+      let stats = base.join(stats);
+      Box::new(BufWriter::new(File::create(&stats).map_err(|err| format!("{}: {}", stats.display(), err))?))
+    },
+    None => Box::new(io::stdout())
+  };
+
+  let (generations, newick, edges) = (config.generations, config.newick.clone(), config.edges.clone());
+  let mut world = World::new(config, programs);
+  let write = |out: &mut Box<dyn Write>, line: String| writeln!(out, "{}", line).map_err(|err| err.to_string());
+  write(&mut out, Stats::csv_header())?;
+  for _ in 0..generations {
+    let stats = world.step();
+    write(&mut out, stats.csv_row())?;
+    if stats.population == 0 {
+      break;
+    }
+  }
+  out.flush().map_err(|err| err.to_string())?;
+
+  let lineage = world.population().lineage();
+  if let Some(file) = newick {
+    export(&base.join(file), lineage.newick())?;
+  }
+  if let Some(file) = edges {
+    export(&base.join(file), lineage.edge_list())?;
+  }
+  Ok(())
+}
+
+fn export(path: &Path, text: String) -> Result<(), String> {
+  fs::write(path, text).map_err(|err| format!("{}: {}", path.display(), err))
 }
@@ -1,14 +1,33 @@
 
 use crate::cpu::WordType;
+use crate::instruction::Instruction;
 
 pub struct Memory {
   mem: Box<[WordType]>,
+  decoded: Option<Box<[Option<Instruction>]>>,
 }
 
 impl Memory {
   pub fn new(size: WordType) -> Self {
     Memory {
-      mem: vec![0; size as usize].into_boxed_slice()
+      mem: vec![0; size as usize].into_boxed_slice(),
+      decoded: None
+    }
+  }
+
+  // Keeps decoded instructions around so repeatedly executed words are only decoded once.
+  // Any write through `set`/`set_range` drops the cached entries it touches.
+  pub fn enable_decode_cache(&mut self) {
+    if self.decoded.is_none() {
+      self.decoded = Some(vec![None; self.mem.len()].into_boxed_slice());
+    }
+  }
+
+  pub fn decode(&mut self, pos: WordType) -> Result<Instruction, MemoryErr> {
+    let value = self.get(pos)?;
+    match &mut self.decoded {
+      Some(cache) => Ok(*cache[pos as usize].get_or_insert_with(|| Instruction::from(value))),
+      None => Ok(Instruction::from(value))
     }
   }
 
@@ -20,9 +39,15 @@ impl Memory {
     }
   }
 
+  // The whole image.
+  pub fn words(&self) -> &[WordType] {
+    &self.mem
+  }
+
   pub fn get_range(&self, pos: WordType, count: WordType) -> Result<&[WordType], MemoryErr> {
-    if (pos + count) <= self.len() {
-      Ok(&self.mem[(pos as usize)..(count as usize)])
+    let end = pos as usize + count as usize;
+    if end <= self.mem.len() {
+      Ok(&self.mem[(pos as usize)..end])
     } else {
       Err(MemoryErr::PointerRangeOverflow(self.len() as WordType, pos, count))
     }
@@ -31,6 +56,9 @@ impl Memory {
   pub fn set (&mut self, pos: WordType, value: WordType) -> Result<(), MemoryErr> {
     if pos < self.len() {
       self.mem[pos as usize] = value;
+      if let Some(cache) = &mut self.decoded {
+        cache[pos as usize] = None;
+      }
       Ok(())
     } else {
       Err(MemoryErr::PointerOutOfRange(self.len() as WordType, pos))
@@ -38,11 +66,15 @@ impl Memory {
   }
 
   pub fn set_range(&mut self, pos: WordType, range: &[WordType]) -> Result<(), MemoryErr> {
-    if (pos as usize + range.len()) <= self.mem.len() {
-      self.mem[(pos as usize)..range.len()].clone_from_slice(range);
+    let end = pos as usize + range.len();
+    if end <= self.mem.len() {
+      self.mem[(pos as usize)..end].clone_from_slice(range);
+      if let Some(cache) = &mut self.decoded {
+        cache[(pos as usize)..end].iter_mut().for_each(|entry| *entry = None);
+      }
       Ok(())
     } else {
-      Err(MemoryErr::PointerRangeOverflow(self.len() as WordType, pos, pos + (range.len() as WordType)))
+      Err(MemoryErr::PointerRangeOverflow(self.len() as WordType, pos, pos.wrapping_add(range.len() as WordType)))
     }
   }
 
@@ -50,6 +82,10 @@ impl Memory {
     self.mem.len() as WordType
   }
 
+  pub fn is_empty(&self) -> bool {
+    self.mem.is_empty()
+  }
+
   #[cfg(test)]
   pub fn raw(&self) -> &[WordType] {
     &self.mem
@@ -61,7 +97,16 @@ impl Memory {
   }
 }
 
-#[derive(Debug, PartialEq)]
+impl From<&[WordType]> for Memory {
+  fn from(words: &[WordType]) -> Memory {
+    Memory {
+      mem: words.to_vec().into_boxed_slice(),
+      decoded: None
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum MemoryErr {
   PointerOutOfRange(WordType, WordType),
   PointerRangeOverflow(WordType, WordType, WordType)
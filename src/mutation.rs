@@ -0,0 +1,212 @@
+use crate::cpu::{ConversionType, WordType};
+use crate::instruction::*;
+use crate::instruction::codes::*;
+use crate::memory::{Memory, MemoryErr};
+use crate::rng::Rng;
+
+const MAX_DUPLICATION: WordType = 8;
+
+// A single change to a memory image. Applying the same mutation to the same image always gives the
+// same result, so a list of these is enough to replay how a genome came about.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mutation {
+  Point { pos: WordType, mask: WordType },
+  Opcode { pos: WordType, opcode: WordType },
+  Operand { pos: WordType, slot: u8, register: u8 },
+  Insertion { pos: WordType, value: WordType },
+  Deletion { pos: WordType },
+  Duplication { pos: WordType, count: WordType },
+}
+
+impl Mutation {
+  pub fn apply(&self, memory: &mut Memory) -> Result<(), MemoryErr> {
+    match *self {
+      Mutation::Point { pos, mask } => {
+        let value = memory.get(pos)?;
+        memory.set(pos, value ^ mask)
+      },
+      Mutation::Opcode { pos, opcode } => {
+        let value = memory.get(pos)?;
+        memory.set(pos, (value & !INSTRUCTION_MASK) | (opcode & INSTRUCTION_MASK))
+      },
+      Mutation::Operand { pos, slot, register } => {
+        let value = memory.get(pos)?;
+        let offset = REGISTER_SIZE * slot as usize + REGISTER_OFFSET;
+        let cleared = value & !(REGISTER_MASK << offset);
+        memory.set(pos, cleared | ((register as WordType & REGISTER_MASK) << offset))
+      },
+      Mutation::Insertion { pos, value } => insert(memory, pos, value),
+      Mutation::Deletion { pos } => delete(memory, pos),
+      Mutation::Duplication { pos, count } => {
+        // The copy goes right after the original; whatever would land past the end is lost.
+        let segment = memory.get_range(pos, count)?.to_vec();
+        for (index, value) in segment.into_iter().enumerate() {
+          let at = pos as usize + count as usize + index;
+          if at >= memory.len() as usize {
+            break;
+          }
+          insert(memory, at as WordType, value)?;
+        }
+        Ok(())
+      }
+    }
+  }
+}
+
+// Picks mutations for a memory image from a seeded PRNG.
+pub struct Mutator {
+  rng: Rng,
+}
+
+impl Mutator {
+  pub fn new(seed: u64) -> Self {
+    Mutator {
+      rng: Rng::new(seed)
+    }
+  }
+
+  pub fn point(&mut self, memory: &Memory) -> Option<Mutation> {
+    let pos = self.position(memory)?;
+    Some(Mutation::Point { pos, mask: 1 << self.rng.below(WordType::BITS as u64) })
+  }
+
+  pub fn opcode(&mut self, memory: &Memory) -> Option<Mutation> {
+    let pos = self.position(memory)?;
+    let opcode = OPCODES[self.rng.below(OPCODES.len() as u64) as usize];
+    Some(Mutation::Opcode { pos, opcode })
+  }
+
+  pub fn operand(&mut self, memory: &Memory) -> Option<Mutation> {
+    let pos = self.position(memory)?;
+    let slots = register_operands(memory.get(pos).ok()? & INSTRUCTION_MASK);
+    if slots == 0 {
+      return None;
+    }
+    Some(Mutation::Operand {
+      pos,
+      slot: self.rng.below(slots as u64) as u8,
+      register: self.rng.below(REGISTER_MASK as u64 + 1) as u8,
+    })
+  }
+
+  pub fn insertion(&mut self, memory: &Memory) -> Option<Mutation> {
+    let pos = self.position(memory)?;
+    Some(Mutation::Insertion { pos, value: self.rng.word() })
+  }
+
+  pub fn deletion(&mut self, memory: &Memory) -> Option<Mutation> {
+    let pos = self.position(memory)?;
+    Some(Mutation::Deletion { pos })
+  }
+
+  pub fn duplication(&mut self, memory: &Memory) -> Option<Mutation> {
+    let pos = self.position(memory)?;
+    let available = memory.len() - pos;
+    let count = 1 + self.rng.below(available.min(MAX_DUPLICATION) as u64) as WordType;
+    Some(Mutation::Duplication { pos, count })
+  }
+
+  // Chooses one of the operators above at random and applies it.
+  pub fn mutate(&mut self, memory: &mut Memory) -> Option<Mutation> {
+    let mutation = match self.rng.below(6) {
+      0 => self.point(memory),
+      1 => self.opcode(memory),
+      2 => self.operand(memory),
+      3 => self.insertion(memory),
+      4 => self.deletion(memory),
+      _ => self.duplication(memory)
+    }?;
+    mutation.apply(memory).ok()?;
+    Some(mutation)
+  }
+
+  // Gives every word of the image a `rate` chance of being hit by one of the operators above and
+  // returns the mutations that were applied, in order.
+  pub fn mutate_words(&mut self, memory: &mut Memory, rate: f64) -> Vec<Mutation> {
+    let mut applied = Vec::new();
+    for _ in 0..memory.len() {
+      if self.rng.chance(rate) {
+        applied.extend(self.mutate(memory));
+      }
+    }
+    applied
+  }
+
+  fn position(&mut self, memory: &Memory) -> Option<WordType> {
+    match memory.len() {
+      0 => None,
+      len => Some(self.rng.below(len as u64) as WordType)
+    }
+  }
+}
+
+fn register_operands(opcode: WordType) -> usize {
+  match opcode {
+    PUSH | POP | CMP_NOT | JMP | BNOT => 1,
+    MOVE_RR | LD | SAV | ADD | SUB | MUL | DIV | CMP_EQ | CMP_NE | CMP_GT | CMP_LT | CMP_XOR | EXT
+      | MEM | BSL | BSR | BXOR | BAND | BOR | BNOR => 2,
+    _ => 0
+  }
+}
+
+// Shifts everything from `pos` up by one word, dropping the last word of the image.
+fn insert(memory: &mut Memory, pos: WordType, value: WordType) -> Result<(), MemoryErr> {
+  let old = memory.words().to_vec();
+  if pos as usize >= old.len() {
+    return Err(MemoryErr::PointerOutOfRange(memory.len(), pos));
+  }
+
+  let pos = pos as usize;
+  let moved = |addr: usize| if addr >= pos { addr + 1 } else { addr };
+  let mut new: Vec<WordType> = Vec::with_capacity(old.len());
+  new.extend(old[..pos].iter().enumerate().map(|(addr, word)| relocate(*word, addr, old.len(), moved)));
+  new.push(value);
+  new.extend(old[pos..(old.len() - 1)].iter().enumerate().map(|(index, word)| {
+    relocate(*word, pos + index, old.len(), moved)
+  }));
+  memory.set_range(0, &new)
+}
+
+// Shifts everything after `pos` down by one word, padding the end of the image with a NOP.
+fn delete(memory: &mut Memory, pos: WordType) -> Result<(), MemoryErr> {
+  let old = memory.words().to_vec();
+  if pos as usize >= old.len() {
+    return Err(MemoryErr::PointerOutOfRange(memory.len(), pos));
+  }
+
+  let pos = pos as usize;
+  let moved = |addr: usize| if addr > pos { addr - 1 } else { addr };
+  let mut new: Vec<WordType> = Vec::with_capacity(old.len());
+  new.extend(old[..pos].iter().enumerate().map(|(addr, word)| relocate(*word, addr, old.len(), moved)));
+  new.extend(old[(pos + 1)..].iter().enumerate().map(|(index, word)| {
+    relocate(*word, pos + 1 + index, old.len(), moved)
+  }));
+  new.push(NOP);
+  memory.set_range(0, &new)
+}
+
+// Rewrites the offset of a PC-relative instruction at `addr` so it still reaches the same word once
+// every address has been passed through `moved`. Offsets that no longer fit are left alone.
+fn relocate<F: Fn(usize) -> usize>(word: WordType, addr: usize, len: usize, moved: F) -> WordType {
+  let (target, landing) = match relative_target(word, addr) {
+    Some((target, landing)) if target >= 0 && (target as usize) < len => (target, landing),
+    _ => return word
+  };
+
+  let relocated = moved(target as usize) as ConversionType - moved(addr) as ConversionType - landing;
+  if relocated < i8::MIN as ConversionType || relocated > i8::MAX as ConversionType {
+    return word;
+  }
+  (word & !ARG_MASK) | (((relocated as i8 as u8) as WordType) << ARG_OFFSET)
+}
+
+// The word reached by a PC-relative instruction at `addr`, along with how far past `PC + offset`
+// that is. Relative jumps land one further because the step increments PC afterwards.
+pub(crate) fn relative_target(word: WordType, addr: usize) -> Option<(ConversionType, ConversionType)> {
+  let (offset, landing) = match Instruction::from(word) {
+    Instruction::LoadRelative(offset) | Instruction::SaveRelative(offset) => (offset, 0),
+    Instruction::JumpRelative(offset, _) => (offset, 1),
+    _ => return None
+  };
+  Some((addr as ConversionType + offset as ConversionType + landing, landing))
+}
@@ -0,0 +1,146 @@
+use std::sync::{Arc, RwLock};
+
+use crate::cpu::*;
+use crate::interpreter::Interpreter;
+use crate::machine::mailbox::{Mailbox, Postbox};
+use crate::memory::Memory;
+use crate::shared_arc::SharedArc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+  // Acquires memory and deals with the rest of the world.
+  Provider,
+  // Judges which other organisms make acceptable mates.
+  Selector,
+}
+
+impl Role {
+  fn index(self) -> usize {
+    match self {
+      Role::Provider => 0,
+      Role::Selector => 1,
+    }
+  }
+
+  pub fn partner(self) -> Role {
+    match self {
+      Role::Provider => Role::Selector,
+      Role::Selector => Role::Provider,
+    }
+  }
+}
+
+// What ties the two halves of a pair together: the region they share and a mailbox each.
+pub(crate) struct Link {
+  shared: SharedArc<Memory>,
+  mailboxes: [Mailbox; 2],
+}
+
+impl Link {
+  pub(crate) fn new(provider: &mut CPU, selector: &mut CPU, shared_size: WordType) -> Self {
+    let shared = Arc::new(RwLock::new(Memory::new(shared_size)));
+    provider.attach_shared(shared.clone());
+    selector.attach_shared(shared.clone());
+    for number in &[INT_PAIR_SEND, INT_PAIR_RECEIVE] {
+      provider.claim(*number);
+      selector.claim(*number);
+    }
+
+    let postbox = Postbox::new();
+    Link {
+      shared,
+      mailboxes: [postbox.register(), postbox.register()],
+    }
+  }
+
+  pub(crate) fn shared_size(&self) -> WordType {
+    self.shared.read().unwrap().len()
+  }
+
+  // Services INT_PAIR_SEND and INT_PAIR_RECEIVE for the `role` half and leaves any other interrupt.
+  pub(crate) fn service(&self, role: Role, cpu: &mut CPU) {
+    let mailbox = &self.mailboxes[role.index()];
+    let done = match cpu.interrupt() {
+      Some(INT_PAIR_SEND) => mailbox.send(role.partner().index() as WordType, cpu.registers[0]),
+      Some(INT_PAIR_RECEIVE) => match mailbox.receive() {
+        Some(message) => {
+          cpu.registers[0] = message.value;
+          true
+        },
+        None => false
+      },
+      _ => return
+    };
+    cpu.take_interrupt();
+    cpu.set_flag(FLAG_COMPARISON, done);
+  }
+}
+
+// The two halves of one Symbios organism. Both CPUs see the same private region at the top of
+// their address space and can pass words to each other: INT_PAIR_SEND sends r0 to the partner,
+// INT_PAIR_RECEIVE puts the oldest word from the partner in r0. FLAG_COMPARISON is set if a word
+// was sent or received. Other interrupts are left for the host. When one half faults, both die.
+// `Population::add_pair` runs a pair as one organism and gives each role its own interrupts.
+pub struct PairMachine {
+  cpus: [CPU; 2],
+  link: Link,
+  fault: Option<(Role, CPUErr)>,
+}
+
+impl PairMachine {
+  pub fn new(mut provider: CPU, mut selector: CPU, shared_size: WordType) -> Self {
+    let link = Link::new(&mut provider, &mut selector, shared_size);
+    PairMachine {
+      cpus: [provider, selector],
+      link,
+      fault: None,
+    }
+  }
+
+  pub(crate) fn into_parts(self) -> (CPU, CPU, Link) {
+    let [provider, selector] = self.cpus;
+    (provider, selector, self.link)
+  }
+
+  pub fn cpu(&self, role: Role) -> &CPU {
+    &self.cpus[role.index()]
+  }
+
+  pub fn cpu_mut(&mut self, role: Role) -> &mut CPU {
+    &mut self.cpus[role.index()]
+  }
+
+  pub fn shared(&self) -> &SharedArc<Memory> {
+    &self.link.shared
+  }
+
+  pub fn fault(&self) -> Option<&(Role, CPUErr)> {
+    self.fault.as_ref()
+  }
+
+  pub fn is_alive(&self) -> bool {
+    self.fault.is_none()
+  }
+
+  // Steps the provider, then the selector. A fault in either one ends the pair.
+  pub fn step(&mut self) -> Result<(), (Role, CPUErr)> {
+    if let Some(fault) = &self.fault {
+      return Err(fault.clone());
+    }
+
+    for role in &[Role::Provider, Role::Selector] {
+      if let Err(err) = self.cpus[role.index()].step() {
+        self.fault = Some((*role, err.clone()));
+        return Err((*role, err));
+      }
+      self.link.service(*role, &mut self.cpus[role.index()]);
+    }
+    Ok(())
+  }
+
+  // The next interrupt the host has to deal with, provider first.
+  pub fn take_interrupt(&mut self) -> Option<(Role, u8)> {
+    [Role::Provider, Role::Selector].iter()
+      .find_map(|role| self.cpus[role.index()].take_interrupt().map(|number| (*role, number)))
+  }
+}
@@ -0,0 +1,486 @@
+use std::collections::HashMap;
+
+use crate::cpu::*;
+use crate::crossover::{ChildPolicy, Crossover};
+use crate::interpreter::Interpreter;
+use crate::lineage::{Lineage, Record};
+use crate::memory::Memory;
+use crate::mutation::{Mutation, Mutator};
+use crate::pair::{Link, PairMachine, Role};
+use crate::rng::Rng;
+use crate::threaded::ThreadedCPU;
+
+// Guests can't hold a 64 bit id, so they name organisms by a handle instead. Handles are unique among
+// the organisms in a population and handed out round-robin, so one is only reused once its organism
+// is gone and every other handle has been given out since. NO_PARTNER is never a handle: organisms
+// added while all the others are taken get it and can't be named by guests.
+//
+// INT_REPRODUCE takes the offspring's genome from `r0..r0 + r1` of the parent's memory. If r2 holds
+// the handle of another living organism, the partner's memory is crossed over into the genome first.
+// On success FLAG_COMPARISON is set and r0 holds the child's handle, otherwise the flag is cleared
+// and r0 holds one of the codes below.
+pub const NO_PARTNER: WordType = WORD_MAX;
+
+pub const REPRODUCE_BAD_RANGE: WordType = 1;
+pub const REPRODUCE_NO_RESOURCES: WordType = 2;
+pub const REPRODUCE_NO_PARTNER: WordType = 3;
+pub const REPRODUCE_FULL: WordType = 4;
+// The provider of a pair only mates with partners its selector accepted the last time it decided on
+// them.
+pub const REPRODUCE_NOT_ACCEPTED: WordType = 5;
+
+// INT_INSPECT maps `r1..r1 + r2` of the organism with handle r0 into the caller's address space, read
+// only, for `PopulationConfig::inspect_cycles` instructions. On success r0 holds the address the
+// window starts at. INT_DECIDE records the caller's verdict on the organism with handle r0, accepting
+// it if r1 is not zero, and unmaps the window. Verdicts on handles nobody holds are not recorded and
+// fail with INSPECT_NO_CANDIDATE. Both report through FLAG_COMPARISON like INT_REPRODUCE.
+pub const INSPECT_BAD_RANGE: WordType = 1;
+pub const INSPECT_NO_CANDIDATE: WordType = 2;
+
+// In a pair only the provider may raise INT_REPRODUCE and only the selector INT_INSPECT and
+// INT_DECIDE. The other half gets this code instead.
+pub const WRONG_ROLE: WordType = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decision {
+  pub chooser: u64,
+  pub candidate: u64,
+  pub accepted: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PopulationConfig {
+  // Energy taken from a metered parent and handed to the child, which is metered with the parent's
+  // cost table. Children of unmetered parents are free and unmetered themselves.
+  pub reproduction_cost: u64,
+  pub max_population: usize,
+  pub child: ChildPolicy,
+  // Chance for every word of a newborn's genome to be hit by one mutation.
+  pub mutation_rate: f64,
+  pub inspect_cycles: u64,
+  // Threads every organism may run at once, see INT_FORK.
+  pub max_threads: usize,
+  // Runs lone organisms on the threaded engine instead of stepping them. Pairs always take turns.
+  pub threaded: bool,
+}
+
+impl Default for PopulationConfig {
+  fn default() -> Self {
+    PopulationConfig {
+      reproduction_cost: 100,
+      max_population: 1024,
+      child: ChildPolicy::default(),
+      mutation_rate: 0.0,
+      inspect_cycles: 100,
+      max_threads: 1,
+      threaded: false,
+    }
+  }
+}
+
+pub struct Organism {
+  pub id: u64,
+  pub handle: WordType,
+  // The whole organism, or the provider of a pair. It holds the genome and the energy either way.
+  pub cpu: CPU,
+  pub selector: Option<CPU>,
+  link: Option<Link>,
+  // The first fault of either half.
+  pub fault: Option<CPUErr>,
+  // Cycles run so far.
+  pub age: u64,
+}
+
+impl Organism {
+  pub fn is_alive(&self) -> bool {
+    self.fault.is_none()
+  }
+
+  pub fn half(&self, role: Role) -> Option<&CPU> {
+    match role {
+      Role::Provider => Some(&self.cpu),
+      Role::Selector => self.selector.as_ref()
+    }
+  }
+
+  fn half_mut(&mut self, role: Role) -> Option<&mut CPU> {
+    match role {
+      Role::Provider => Some(&mut self.cpu),
+      Role::Selector => self.selector.as_mut()
+    }
+  }
+
+  fn may(&self, role: Role, number: u8) -> bool {
+    self.selector.is_none() || matches!(
+      (role, number),
+      (Role::Provider, INT_REPRODUCE) | (Role::Selector, INT_INSPECT) | (Role::Selector, INT_DECIDE)
+    )
+  }
+}
+
+// The host side of a population of CPUs: runs them and services the interrupts they raise.
+pub struct Population {
+  config: PopulationConfig,
+  organisms: Vec<Organism>,
+  next_id: u64,
+  handles: HashMap<WordType, u64>,
+  next_handle: WordType,
+  seed: u64,
+  crossover: Crossover,
+  mutator: Mutator,
+  lineage: Lineage,
+  decisions: Vec<Decision>,
+}
+
+impl Population {
+  pub fn new(config: PopulationConfig, seed: u64) -> Self {
+    Population {
+      config,
+      organisms: Vec::new(),
+      next_id: 0,
+      handles: HashMap::new(),
+      next_handle: 0,
+      seed,
+      crossover: Crossover::new(seed),
+      mutator: Mutator::new(!seed),
+      lineage: Lineage::new(),
+      decisions: Vec::new(),
+    }
+  }
+
+  // Every organism's INT_RANDOM stream is seeded from the population seed and its id, the
+  // interrupts serviced here are claimed so they never reach guest handlers, and the thread limit
+  // comes from the config.
+  pub fn add(&mut self, cpu: CPU) -> u64 {
+    self.birth(cpu, None, Vec::new(), Vec::new())
+  }
+
+  // Runs `pair` as one organism that lives and dies as a whole. Its children are pairs too: the
+  // provider's genome is inherited as usual and the selector's memory is copied over unchanged.
+  pub fn add_pair(&mut self, pair: PairMachine) -> u64 {
+    let (provider, selector, link) = pair.into_parts();
+    self.birth(provider, Some((selector, link)), Vec::new(), Vec::new())
+  }
+
+  fn birth(&mut self, mut cpu: CPU, pair: Option<(CPU, Link)>, parents: Vec<u64>, mutations: Vec<Mutation>) -> u64 {
+    let id = self.next_id;
+    self.next_id += 1;
+    let handle = self.allocate_handle().unwrap_or(NO_PARTNER);
+    if handle != NO_PARTNER {
+      self.handles.insert(handle, id);
+    }
+    let (mut selector, link) = match pair {
+      Some((selector, link)) => (Some(selector), Some(link)),
+      None => (None, None)
+    };
+    cpu.set_rng(Rng::for_machine(self.seed, id));
+    if let Some(selector) = &mut selector {
+      selector.set_rng(Rng::for_machine(!self.seed, id));
+    }
+    for cpu in std::iter::once(&mut cpu).chain(selector.as_mut()) {
+      for number in &[INT_REPRODUCE, INT_INSPECT, INT_DECIDE] {
+        cpu.claim(*number);
+      }
+      cpu.set_max_threads(self.config.max_threads);
+    }
+    let generation = parents.iter()
+      .filter_map(|parent| self.lineage.get(*parent))
+      .map(|parent| parent.generation + 1)
+      .max()
+      .unwrap_or(0);
+    self.lineage.record(Record { id, parents, generation, mutations });
+    self.organisms.push(Organism { id, handle, cpu, selector, link, fault: None, age: 0 });
+    id
+  }
+
+  fn allocate_handle(&mut self) -> Option<WordType> {
+    if self.handles.len() >= NO_PARTNER as usize {
+      return None;
+    }
+    loop {
+      let handle = self.next_handle;
+      self.next_handle = if handle + 1 == NO_PARTNER { 0 } else { handle + 1 };
+      if !self.handles.contains_key(&handle) {
+        return Some(handle);
+      }
+    }
+  }
+
+  // Where the organism a guest calls `handle` is, skipping `index`, the guest itself.
+  fn find_handle(&self, index: usize, handle: WordType) -> Option<usize> {
+    let id = *self.handles.get(&handle)?;
+    self.organisms.iter()
+      .enumerate()
+      .position(|(other, organism)| other != index && organism.id == id)
+  }
+
+  pub fn get(&self, id: u64) -> Option<&Organism> {
+    self.organisms.iter().find(|organism| organism.id == id)
+  }
+
+  pub fn get_mut(&mut self, id: u64) -> Option<&mut Organism> {
+    self.organisms.iter_mut().find(|organism| organism.id == id)
+  }
+
+  pub fn by_handle(&self, handle: WordType) -> Option<&Organism> {
+    self.get(*self.handles.get(&handle)?)
+  }
+
+  pub fn grant(&mut self, id: u64, amount: u64) -> bool {
+    match self.get_mut(id) {
+      Some(organism) => {
+        organism.cpu.grant(amount);
+        true
+      },
+      None => false
+    }
+  }
+
+  // Moves up to `amount` energy between two organisms and returns how much actually moved.
+  pub fn transfer(&mut self, from: u64, to: u64, amount: u64) -> u64 {
+    if from == to || self.get(to).and_then(|organism| organism.cpu.energy()).is_none() {
+      return 0;
+    }
+
+    let taken = match self.get_mut(from) {
+      Some(organism) => organism.cpu.take_energy(amount),
+      None => 0
+    };
+    self.grant(to, taken);
+    taken
+  }
+
+  pub fn lineage(&self) -> &Lineage {
+    &self.lineage
+  }
+
+  // Every verdict given through INT_DECIDE, oldest first.
+  pub fn decisions(&self) -> &[Decision] {
+    &self.decisions
+  }
+
+  pub fn organisms(&self) -> &[Organism] {
+    &self.organisms
+  }
+
+  pub fn organisms_mut(&mut self) -> &mut [Organism] {
+    &mut self.organisms
+  }
+
+  // The id the next organism added or born will get.
+  pub fn next_id(&self) -> u64 {
+    self.next_id
+  }
+
+  // Drops every organism `keep` rejects and returns how many that was. Their handles are freed.
+  pub fn retain<F: FnMut(&Organism) -> bool>(&mut self, mut keep: F) -> usize {
+    let before = self.organisms.len();
+    let handles = &mut self.handles;
+    self.organisms.retain(|organism| {
+      let kept = keep(organism);
+      if !kept && handles.get(&organism.handle) == Some(&organism.id) {
+        handles.remove(&organism.handle);
+      }
+      kept
+    });
+    before - self.organisms.len()
+  }
+
+  pub fn living(&self) -> usize {
+    self.organisms.iter().filter(|organism| organism.is_alive()).count()
+  }
+
+  // Steps every living organism `cycles` times. Organisms born along the way start on the next run.
+  pub fn run(&mut self, cycles: u64) {
+    for index in 0..self.organisms.len() {
+      if self.config.threaded && self.organisms[index].selector.is_none() {
+        self.run_threaded(index, cycles);
+        continue;
+      }
+
+      for _ in 0..cycles {
+        if !self.organisms[index].is_alive() {
+          break;
+        }
+
+        self.organisms[index].age += 1;
+        self.step_half(index, Role::Provider);
+        self.step_half(index, Role::Selector);
+      }
+    }
+  }
+
+  // Runs a lone organism for up to `cycles` cycles on the threaded engine, which hands the CPU back
+  // for every interrupt the population services.
+  fn run_threaded(&mut self, index: usize, cycles: u64) {
+    let mut remaining = cycles;
+    while remaining > 0 && self.organisms[index].is_alive() {
+      let organism = &mut self.organisms[index];
+      let cpu = std::mem::replace(&mut organism.cpu, CPU::new(Memory::new(0), 0, 0));
+      let mut engine = ThreadedCPU::new(cpu);
+      let (executed, res) = engine.run(remaining.min(usize::MAX as u64) as usize);
+      organism.cpu = engine.into_inner();
+      organism.age += executed as u64;
+      remaining -= executed as u64;
+      if let Err(err) = res {
+        organism.fault = Some(err);
+      }
+
+      if let Some(number) = organism.cpu.take_interrupt() {
+        self.service(index, Role::Provider, number);
+      }
+    }
+  }
+
+  // Steps one half of a living organism, if it has that half.
+  fn step_half(&mut self, index: usize, role: Role) {
+    let organism = &mut self.organisms[index];
+    if !organism.is_alive() {
+      return;
+    }
+    let cpu = match role {
+      Role::Provider => &mut organism.cpu,
+      Role::Selector => match &mut organism.selector {
+        Some(selector) => selector,
+        None => return
+      }
+    };
+    if let Err(err) = cpu.step() {
+      organism.fault = Some(err);
+    }
+    if let Some(link) = &organism.link {
+      link.service(role, cpu);
+    }
+
+    if let Some(number) = cpu.take_interrupt() {
+      self.service(index, role, number);
+    }
+  }
+
+  fn service(&mut self, index: usize, role: Role, number: u8) {
+    let res = match number {
+      INT_REPRODUCE | INT_INSPECT | INT_DECIDE if !self.organisms[index].may(role, number) => Err(WRONG_ROLE),
+      INT_REPRODUCE => self.reproduce(index),
+      INT_INSPECT => self.inspect(index, role),
+      INT_DECIDE => self.decide(index, role),
+      _ => return
+    };
+
+    let cpu = match self.organisms[index].half_mut(role) {
+      Some(cpu) => cpu,
+      None => return
+    };
+    match res {
+      Ok(value) => {
+        cpu.registers[0] = value;
+        cpu.set_flag(FLAG_COMPARISON, true);
+      },
+      Err(code) => {
+        cpu.registers[0] = code;
+        cpu.set_flag(FLAG_COMPARISON, false);
+      }
+    }
+  }
+
+  fn inspect(&mut self, index: usize, role: Role) -> Result<WordType, WordType> {
+    let registers = match self.organisms[index].half(role) {
+      Some(cpu) => cpu.registers,
+      None => return Err(WRONG_ROLE)
+    };
+    let (candidate, start, count) = (registers[0], registers[1], registers[2]);
+    let candidate = self.find_handle(index, candidate)
+      .map(|other| &self.organisms[other])
+      .filter(|organism| organism.is_alive())
+      .ok_or(INSPECT_NO_CANDIDATE)?;
+    let words = candidate.cpu.memory.get_range(start, count)
+      .map_err(|_| INSPECT_BAD_RANGE)?
+      .to_vec();
+
+    let cycles = self.config.inspect_cycles;
+    let cpu = self.organisms[index].half_mut(role).ok_or(WRONG_ROLE)?;
+    Ok(cpu.map_window(words, cycles))
+  }
+
+  fn decide(&mut self, index: usize, role: Role) -> Result<WordType, WordType> {
+    let organism = &mut self.organisms[index];
+    let chooser = organism.id;
+    let cpu = organism.half_mut(role).ok_or(WRONG_ROLE)?;
+    let (candidate, verdict) = (cpu.registers[0], cpu.registers[1]);
+    cpu.unmap_window();
+    let other = self.find_handle(index, candidate).ok_or(INSPECT_NO_CANDIDATE)?;
+    self.decisions.push(Decision {
+      chooser,
+      candidate: self.organisms[other].id,
+      accepted: verdict != 0,
+    });
+    Ok(candidate)
+  }
+
+  fn reproduce(&mut self, index: usize) -> Result<WordType, WordType> {
+    if self.living() >= self.config.max_population || self.handles.len() >= NO_PARTNER as usize {
+      return Err(REPRODUCE_FULL);
+    }
+
+    let parent = &self.organisms[index];
+    let cost = self.config.reproduction_cost;
+    if parent.cpu.energy().is_some_and(|energy| energy < cost) {
+      return Err(REPRODUCE_NO_RESOURCES);
+    }
+
+    let (start, count, partner) = (parent.cpu.registers[0], parent.cpu.registers[1], parent.cpu.registers[2]);
+    let genome = match parent.cpu.memory.get_range(start, count) {
+      Ok(words) if !words.is_empty() => Memory::from(words),
+      _ => return Err(REPRODUCE_BAD_RANGE)
+    };
+
+    let mut parents = vec![parent.id];
+    let mut genome = if partner == NO_PARTNER {
+      genome
+    } else {
+      let mate = self.find_handle(index, partner)
+        .filter(|&other| self.organisms[other].is_alive());
+      match mate {
+        Some(other) => {
+          let mate = &self.organisms[other];
+          if parent.selector.is_some() && !self.accepted(parent.id, mate.id) {
+            return Err(REPRODUCE_NOT_ACCEPTED);
+          }
+          parents.push(mate.id);
+          self.crossover.aligned(&mate.cpu.memory, &genome)
+        },
+        None => return Err(REPRODUCE_NO_PARTNER)
+      }
+    };
+    let mutations = self.mutator.mutate_words(&mut genome, self.config.mutation_rate);
+
+    let parent = &mut self.organisms[index].cpu;
+    let costs = parent.costs().cloned();
+    parent.take_energy(cost);
+
+    let mut child = self.crossover.child(&self.config.child, genome);
+    if let Some(costs) = costs {
+      child.meter(cost, costs);
+    }
+    let parent = &self.organisms[index];
+    let pair = match (&parent.selector, &parent.link) {
+      (Some(selector), Some(link)) => {
+        let memory = Memory::from(selector.memory.words());
+        let size = link.shared_size();
+        let mut selector = self.crossover.child(&self.config.child, memory);
+        let link = Link::new(&mut child, &mut selector, size);
+        Some((selector, link))
+      },
+      _ => None
+    };
+    let id = self.birth(child, pair, parents, mutations);
+    Ok(self.get(id).map_or(NO_PARTNER, |child| child.handle))
+  }
+
+  // Whether the last verdict `chooser` gave on `candidate` accepted it.
+  fn accepted(&self, chooser: u64, candidate: u64) -> bool {
+    self.decisions.iter()
+      .rev()
+      .find(|decision| decision.chooser == chooser && decision.candidate == candidate)
+      .is_some_and(|decision| decision.accepted)
+  }
+}
@@ -0,0 +1,58 @@
+use crate::cpu::WordType;
+
+// xorshift64*. Small, fast and, more importantly, the same on every platform so runs can be
+// reproduced from their seed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rng {
+  state: u64,
+}
+
+impl Rng {
+  pub fn new(seed: u64) -> Self {
+    // Run the seed through splitmix64 so that small or zero seeds still give a usable state.
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    Rng {
+      state: if z == 0 { 0x9E37_79B9_7F4A_7C15 } else { z }
+    }
+  }
+
+  // Independent streams for every machine of a simulation, so a machine's draws don't depend on how
+  // many other machines there are or in which order they run.
+  pub fn for_machine(seed: u64, id: u64) -> Self {
+    Rng::new(Rng::new(seed).next_u64() ^ id)
+  }
+
+  // Everything needed to pick the stream back up with `from_state`.
+  pub fn state(&self) -> u64 {
+    self.state
+  }
+
+  pub fn from_state(state: u64) -> Self {
+    Rng {
+      state: if state == 0 { 0x9E37_79B9_7F4A_7C15 } else { state }
+    }
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.state ^= self.state >> 12;
+    self.state ^= self.state << 25;
+    self.state ^= self.state >> 27;
+    self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+  }
+
+  pub fn word(&mut self) -> WordType {
+    (self.next_u64() >> 48) as WordType
+  }
+
+  // Uniform in `0..bound`. `bound` must not be zero.
+  pub fn below(&mut self, bound: u64) -> u64 {
+    ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+  }
+
+  pub fn chance(&mut self, probability: f64) -> bool {
+    ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < probability
+  }
+}
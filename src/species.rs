@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use crate::cpu::WordType;
+use crate::instruction::Instruction;
+use crate::memory::Memory;
+use crate::population::Organism;
+
+const FNV_OFFSET: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+// FNV-1a over the words of the image, low byte first. Unlike `DefaultHasher` it is fixed, so hashes
+// can be compared between runs and machines.
+pub fn genome_hash(memory: &Memory) -> u64 {
+  let mut hash = FNV_OFFSET;
+  for word in memory.words() {
+    for byte in word.to_le_bytes().iter() {
+      hash ^= *byte as u64;
+      hash = hash.wrapping_mul(FNV_PRIME);
+    }
+  }
+  hash
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+  // Words that differ at the same position, plus the difference in length.
+  Hamming,
+  // Instructions inserted, deleted or replaced to turn one decoded image into the other.
+  Edit,
+}
+
+impl Metric {
+  pub fn distance(&self, first: &Memory, second: &Memory) -> usize {
+    match self {
+      Metric::Hamming => hamming(first.words(), second.words()),
+      Metric::Edit => {
+        let first: Vec<Instruction> = first.words().iter().map(Instruction::from).collect();
+        let second: Vec<Instruction> = second.words().iter().map(Instruction::from).collect();
+        edit(&first, &second)
+      }
+    }
+  }
+}
+
+fn hamming(first: &[WordType], second: &[WordType]) -> usize {
+  let differing = first.iter().zip(second.iter()).filter(|(a, b)| a != b).count();
+  differing + first.len().max(second.len()) - first.len().min(second.len())
+}
+
+// Levenshtein distance, keeping only the previous row of the table.
+fn edit(first: &[Instruction], second: &[Instruction]) -> usize {
+  let mut previous: Vec<usize> = (0..=second.len()).collect();
+  let mut current = vec![0; second.len() + 1];
+  for (i, a) in first.iter().enumerate() {
+    current[0] = i + 1;
+    for (j, b) in second.iter().enumerate() {
+      let replace = previous[j] + if a == b { 0 } else { 1 };
+      current[j + 1] = replace.min(previous[j + 1] + 1).min(current[j] + 1);
+    }
+    std::mem::swap(&mut previous, &mut current);
+  }
+  previous[second.len()]
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Species {
+  // The organism every other member was compared against.
+  pub representative: u64,
+  pub members: Vec<u64>,
+}
+
+// Groups organisms into species in a single pass: each joins the first species whose representative
+// is within `threshold` of it, or founds a new one. Identical genomes always end up together, so
+// every genotype is only compared once.
+pub fn cluster(organisms: &[Organism], metric: Metric, threshold: usize) -> Vec<Species> {
+  let mut species: Vec<Species> = Vec::new();
+  let mut representatives: Vec<&Memory> = Vec::new();
+  let mut placed: HashMap<u64, usize> = HashMap::new();
+
+  for organism in organisms {
+    let memory = &organism.cpu.memory;
+    let index = *placed.entry(genome_hash(memory)).or_insert_with(|| {
+      match representatives.iter().position(|other| metric.distance(other, memory) <= threshold) {
+        Some(index) => index,
+        None => {
+          species.push(Species { representative: organism.id, members: Vec::new() });
+          representatives.push(memory);
+          species.len() - 1
+        }
+      }
+    });
+    species[index].members.push(organism.id);
+  }
+
+  species
+}
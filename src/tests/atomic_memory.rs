@@ -0,0 +1,39 @@
+use std::thread;
+
+use crate::machine::atomic_memory::AtomicMemory as Subject;
+use crate::machine::memory::MemoryErr;
+
+#[test]
+fn get_and_set() {
+  let subject = Subject::new_raw(8);
+  assert_eq!(8, subject.len());
+  assert_eq!(Ok(0), subject.get(7));
+  assert_eq!(Ok(()), subject.set(7, 16));
+  assert_eq!(Ok(16), subject.get(7));
+  assert_eq!(Err(MemoryErr::PointerOutOfRange(8, 8)), subject.get(8));
+  assert_eq!(Err(MemoryErr::PointerOutOfRange(8, 9)), subject.set(9, 1));
+}
+
+#[test]
+fn update() {
+  let subject = Subject::new_raw(4);
+  assert_eq!(Ok(()), subject.set(2, 5));
+  assert_eq!(Ok(5), subject.update(2, |old| old * 3));
+  assert_eq!(Ok(15), subject.get(2));
+  assert_eq!(Err(MemoryErr::PointerOutOfRange(4, 4)), subject.update(4, |old| old));
+}
+
+#[test]
+fn shared_between_threads() {
+  let subject = Subject::new(2);
+  thread::scope(|scope| {
+    for _ in 0..8 {
+      scope.spawn(|| {
+        for _ in 0..1000 {
+          subject.update(1, |old| old + 1).unwrap();
+        }
+      });
+    }
+  });
+  assert_eq!(Ok(8000), subject.get(1));
+}
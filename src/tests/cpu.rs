@@ -0,0 +1,753 @@
+use std::sync::{Arc, RwLock};
+
+use crate::cpu::*;
+use crate::cpu::CPU as Subject;
+use crate::instruction::Instruction;
+use crate::instruction::codes::*;
+use crate::memory::{Memory, MemoryErr};
+use crate::threaded::ThreadedCPU;
+
+// Every test image: code and data at fixed addresses in memory just big enough for them, and
+// whatever registers, stack, vector table and thread limit the test asks for.
+#[derive(Default)]
+struct Program {
+  words: Vec<WordType>,
+  registers: Vec<(usize, WordType)>,
+  stack: Option<StackMode>,
+  vectors: Option<WordType>,
+  max_threads: Option<usize>,
+}
+
+impl Program {
+  fn new(code: &[Instruction]) -> Self {
+    Program::default().code(0, code)
+  }
+
+  fn code(self, at: usize, code: &[Instruction]) -> Self {
+    let words: Vec<WordType> = code.iter().map(WordType::from).collect();
+    self.words(at, &words)
+  }
+
+  fn words(mut self, at: usize, words: &[WordType]) -> Self {
+    self = self.size(at + words.len());
+    self.words[at..at + words.len()].copy_from_slice(words);
+    self
+  }
+
+  fn size(mut self, size: usize) -> Self {
+    if self.words.len() < size {
+      self.words.resize(size, 0);
+    }
+    self
+  }
+
+  fn registers(mut self, registers: &[(usize, WordType)]) -> Self {
+    self.registers.extend_from_slice(registers);
+    self
+  }
+
+  fn stack(mut self, mode: StackMode) -> Self {
+    self.stack = Some(mode);
+    self
+  }
+
+  // Points `vectors` of a table at `base` to `handler`.
+  fn vectors(mut self, base: usize, vectors: &[WordType], handler: WordType) -> Self {
+    self = self.size(base);
+    for vector in vectors {
+      self = self.words(base + *vector as usize, &[handler]);
+    }
+    self.vectors = Some(base as WordType);
+    self
+  }
+
+  fn threads(mut self, max_threads: usize) -> Self {
+    self.max_threads = Some(max_threads);
+    self
+  }
+
+  fn build(&self) -> Subject {
+    let stack = self.stack.unwrap_or(StackMode::Separate(8));
+    let mut cpu = Subject::with_stack(Memory::from(&self.words[..]), 0, stack);
+    for (reg, value) in &self.registers {
+      cpu.set_register(*reg, *value);
+    }
+    if let Some(base) = self.vectors {
+      cpu.set_vectors(base);
+    }
+    if let Some(max_threads) = self.max_threads {
+      cpu.set_max_threads(max_threads);
+    }
+    cpu
+  }
+}
+
+fn cpu(code: &[Instruction], registers: &[(usize, WordType)]) -> Subject {
+  Program::new(code).registers(registers).build()
+}
+
+// Runs a single instruction and returns r1 and FLAGS, checking the threaded engine agrees.
+fn run(instruction: Instruction, r1: WordType, r2: WordType) -> (Result<(), CPUErr>, WordType, WordType) {
+  let mut subject = cpu(&[instruction], &[(1, r1), (2, r2)]);
+  let res = subject.step();
+
+  let mut threaded = ThreadedCPU::new(cpu(&[instruction], &[(1, r1), (2, r2)]));
+  let (_, threaded_res) = threaded.run(1);
+  assert_eq!(res, threaded_res);
+  assert_eq!(subject.register(1), threaded.cpu().register(1));
+  assert_eq!(subject.register(13), threaded.cpu().register(13));
+
+  (res, subject.register(1), subject.register(13))
+}
+
+const OVERFLOW: WordType = 0x0001;
+const COMPARISON: WordType = 0x0002;
+const SIGNED_OVERFLOW: WordType = 0x0004;
+const ZERO: WordType = 0x0008;
+const NEGATIVE: WordType = 0x0010;
+const CARRY: WordType = 0x0020;
+
+#[test]
+fn signed_overflow() {
+  // 0x7FFF + 1 carries into the sign bit without carrying out of the word.
+  assert_eq!((Ok(()), 0x8000, SIGNED_OVERFLOW | NEGATIVE), run(Instruction::Add(1, 2), 0x7FFF, 1));
+  // -1 + 1 carries out of the word without changing sign wrongly.
+  assert_eq!((Ok(()), 0, OVERFLOW | CARRY | ZERO), run(Instruction::Add(1, 2), 0xFFFF, 1));
+  assert_eq!((Ok(()), 0x7FFF, SIGNED_OVERFLOW), run(Instruction::Subtract(1, 2), 0x8000, 1));
+  assert_eq!((Ok(()), 0xFFFF, OVERFLOW | CARRY | NEGATIVE), run(Instruction::Subtract(1, 2), 0, 1));
+  assert_eq!((Ok(()), 0xFFFE, OVERFLOW | CARRY | NEGATIVE), run(Instruction::Multiply(1, 2), 0xFFFF, 2));
+  assert_eq!((Ok(()), 0x0000, OVERFLOW | SIGNED_OVERFLOW | CARRY | ZERO), run(Instruction::Multiply(1, 2), 0x4000, 4));
+}
+
+#[test]
+fn signed_comparison() {
+  assert_eq!((Ok(()), 0xFFFF, 0), run(Instruction::SignedGreaterThan(1, 2), 0xFFFF, 1));
+  assert_eq!((Ok(()), 0xFFFF, COMPARISON), run(Instruction::GreaterThan(1, 2), 0xFFFF, 1));
+  assert_eq!((Ok(()), 0xFFFF, COMPARISON), run(Instruction::SignedLessThan(1, 2), 0xFFFF, 1));
+  assert_eq!((Ok(()), 0x8000, COMPARISON), run(Instruction::SignedLessThan(1, 2), 0x8000, 0x7FFF));
+  assert_eq!((Ok(()), 5, 0), run(Instruction::SignedLessThan(1, 2), 5, 5));
+}
+
+#[test]
+fn arithmetic_shift_right() {
+  assert_eq!((Ok(()), 0xFFF0, NEGATIVE), run(Instruction::ArithmeticShiftRight(1, 2), 0xFF00, 4));
+  assert_eq!((Ok(()), 0x00F0, 0), run(Instruction::ArithmeticShiftRight(1, 2), 0x0F00, 4));
+  assert_eq!((Ok(()), 0xFFFF, OVERFLOW | NEGATIVE), run(Instruction::ArithmeticShiftRight(1, 2), 0x8000, 16));
+  assert_eq!((Ok(()), 0, OVERFLOW | CARRY | ZERO), run(Instruction::ArithmeticShiftRight(1, 2), 0x7000, 200));
+
+  // Unlike ASR, BSR only shifts by the amount modulo the word size.
+  assert_eq!((Ok(()), 0x00F0, OVERFLOW), run(Instruction::BitShiftRight(1, 2), 0x0F00, 20));
+}
+
+#[test]
+fn signed_divide() {
+  assert_eq!((Ok(()), (-3i16) as WordType, NEGATIVE), run(Instruction::SignedDivide(1, 2), (-7i16) as WordType, 2));
+  assert_eq!((Ok(()), 3, 0), run(Instruction::SignedDivide(1, 2), (-7i16) as WordType, (-2i16) as WordType));
+  assert_eq!((Ok(()), 0x8000, SIGNED_OVERFLOW | NEGATIVE), run(Instruction::SignedDivide(1, 2), 0x8000, 0xFFFF));
+  assert_eq!(Err(CPUErr::DivideByZero), run(Instruction::SignedDivide(1, 2), 7, 0).0);
+}
+
+#[test]
+fn divide_clears_arithmetic_flags() {
+  // Flags left over from an earlier overflowing add don't survive a division, even a failed one.
+  let stale = OVERFLOW | SIGNED_OVERFLOW | CARRY | NEGATIVE;
+  for (divisor, res, flags) in &[(2, Ok(()), 0), (0, Err(CPUErr::DivideByZero), NEGATIVE)] {
+    for instruction in &[Instruction::Divide(1, 2), Instruction::SignedDivide(1, 2)] {
+      let mut subject = cpu(&[*instruction], &[(1, 6), (2, *divisor), (13, stale)]);
+      assert_eq!(*res, subject.step());
+      assert_eq!(*flags, subject.register(13));
+    }
+  }
+}
+
+#[test]
+fn result_flags() {
+  assert_eq!((Ok(()), 0, ZERO), run(Instruction::BitAnd(1, 2), 0xF0F0, 0x0F0F));
+  assert_eq!((Ok(()), 0xFFFF, NEGATIVE), run(Instruction::BitOr(1, 2), 0xF0F0, 0x0F0F));
+  assert_eq!((Ok(()), 0, ZERO), run(Instruction::BitNor(1, 2), 0xF0F0, 0x0F0F));
+  assert_eq!((Ok(()), 0x8000, NEGATIVE), run(Instruction::BitXor(1, 2), 0x8001, 1));
+  assert_eq!((Ok(()), 0, ZERO), run(Instruction::BitNot(1), 0xFFFF, 0));
+  assert_eq!((Ok(()), 0, ZERO), run(Instruction::Divide(1, 2), 3, 4));
+  assert_eq!((Ok(()), 2, 0), run(Instruction::Subtract(1, 2), 5, 3));
+}
+
+#[test]
+fn shift_carry() {
+  assert_eq!((Ok(()), 0x0002, CARRY), run(Instruction::BitShiftLeft(1, 2), 0x8001, 1));
+  assert_eq!((Ok(()), 0x4000, CARRY), run(Instruction::BitShiftRight(1, 2), 0x8001, 1));
+  assert_eq!((Ok(()), 0xA000, NEGATIVE), run(Instruction::BitShiftLeft(1, 2), 0x000A, 12));
+  assert_eq!((Ok(()), 0x0000, CARRY | ZERO), run(Instruction::BitShiftRight(1, 2), 0x0008, 4));
+  assert_eq!((Ok(()), 0x0008, 0), run(Instruction::BitShiftRight(1, 2), 0x0008, 0));
+}
+
+// Runs a jump to 6 with FLAGS preset and returns whether it was taken, checking the threaded engine
+// agrees.
+fn jumps(instruction: Instruction, flags: WordType) -> Result<bool, CPUErr> {
+  let mut subject = cpu(&[instruction], &[(3, 6), (13, flags)]);
+  let res = subject.step();
+
+  let mut threaded = ThreadedCPU::new(cpu(&[instruction], &[(3, 6), (13, flags)]));
+  let (_, threaded_res) = threaded.run(1);
+  assert_eq!(res, threaded_res);
+  assert_eq!(subject.register(14), threaded.cpu().register(14));
+
+  res.map(|_| subject.register(14) == 6)
+}
+
+#[test]
+fn jump_conditions() {
+  let cases = [
+    (COND_ALWAYS, 0, true),
+    (COND_COMPARISON, COMPARISON, true),
+    (COND_NOT_COMPARISON, COMPARISON, false),
+    (COND_OVERFLOW, OVERFLOW, true),
+    (COND_ZERO, ZERO, true),
+    (COND_ZERO, NEGATIVE | CARRY, false),
+    (COND_NOT_ZERO, ZERO, false),
+    (COND_NOT_ZERO, NEGATIVE, true),
+    (COND_NEGATIVE, NEGATIVE, true),
+    (COND_NEGATIVE, SIGNED_OVERFLOW, false),
+    (COND_CARRY, CARRY, true),
+    (COND_CARRY, OVERFLOW, false),
+  ];
+  for (condition, flags, taken) in cases.iter() {
+    assert_eq!(Ok(*taken), jumps(Instruction::Jump(3, *condition), *flags), "JMP {}", condition);
+    assert_eq!(Ok(*taken), jumps(Instruction::JumpRelative(5, *condition), *flags), "JREL {}", condition);
+  }
+
+  // Not-overflow only fits JMP's wider condition field.
+  assert_eq!(Ok(true), jumps(Instruction::Jump(3, COND_NOT_OVERFLOW), CARRY));
+  assert_eq!(Ok(false), jumps(Instruction::Jump(3, COND_NOT_OVERFLOW), OVERFLOW));
+  assert_eq!(Err(CPUErr::InvalidJumpCondition(9)), jumps(Instruction::Jump(3, 9), 0));
+}
+
+#[test]
+fn branches_on_result() {
+  // Counts r1 down to zero, adding r2 into r4 each time round.
+  let code = [
+    Instruction::Add(4, 2),
+    Instruction::Subtract(1, 5),
+    Instruction::JumpRelative(-3, COND_NOT_ZERO),
+    Instruction::Nop,
+  ];
+  let mut subject = cpu(&code, &[(1, 3), (2, 7), (5, 1)]);
+  for _ in 0..9 {
+    assert_eq!(Ok(()), subject.step());
+  }
+  assert_eq!(21, subject.register(4));
+  assert_eq!(3, subject.register(14));
+  assert_eq!(ZERO, subject.register(13) & ZERO);
+}
+
+#[test]
+fn offset_addressing() {
+  // Copies the second field of the record at r1 into the third, then reads the first back.
+  let program = Program::default().words(0, &[
+    Instruction::LoadOffset(3, 1).into(), 1,
+    Instruction::SaveOffset(1, 3).into(), 2,
+    Instruction::LoadOffset(4, 2).into(), (-2i16) as WordType,
+    Instruction::Nop.into(), 0x1111, 0x2222, 0,
+  ]).registers(&[(1, 7), (2, 9)]);
+  let mut subject = program.build();
+  let mut threaded = ThreadedCPU::new(program.build());
+
+  for _ in 0..3 {
+    assert_eq!(Ok(()), subject.step());
+  }
+  assert_eq!((3, Ok(())), threaded.run(3));
+  for cpu in [&subject, threaded.cpu()].iter() {
+    assert_eq!(0x2222, cpu.register(3));
+    assert_eq!(0x1111, cpu.register(4));
+    assert_eq!(Ok(0x2222), cpu.memory.get(9));
+    assert_eq!(6, cpu.register(14));
+  }
+}
+
+#[test]
+fn offset_faults() {
+  // The offset word itself is missing.
+  let mut subject = cpu(&[Instruction::LoadOffset(3, 1)], &[]);
+  assert_eq!(Err(CPUErr::MemoryErr(MemoryErr::PointerOutOfRange(1, 1))), subject.step());
+
+  let mut subject = Program::new(&[Instruction::SaveOffset(1, 3)]).words(1, &[40]).build();
+  assert_eq!(Err(CPUErr::MemoryErr(MemoryErr::PointerOutOfRange(2, 40))), subject.step());
+  // A failed access doesn't skip the offset word.
+  assert_eq!(1, subject.register(14));
+}
+
+#[test]
+fn offset_store_rewrites_code() {
+  // Overwrites the Nop after it with a BitNot of r5, which the threaded engine must pick up.
+  let program = Program::new(&[Instruction::SaveOffset(0, 1)]).words(1, &[2]).code(2, &[Instruction::Nop]);
+  let mut threaded = ThreadedCPU::new(program.registers(&[(1, Instruction::BitNot(5).into())]).build());
+  assert_eq!((2, Ok(())), threaded.run(2));
+  assert_eq!(0xFFFF, threaded.cpu().register(5));
+}
+
+#[test]
+fn stack_frames() {
+  // Pushes r1 and r2, reserves two locals, fills one from the first push and drops the frame.
+  let code = [
+    Instruction::PushRegister(1),
+    Instruction::PushRegister(2),
+    Instruction::AdjustStack(2),
+    Instruction::Peek(3, 3),
+    Instruction::Poke(0, 3),
+    Instruction::Peek(4, 0),
+    Instruction::Peek(5, 2),
+    Instruction::AdjustStack(-3),
+    Instruction::PopRegister(6),
+  ];
+  let mut subject = cpu(&code, &[(1, 11), (2, 22)]);
+  for _ in 0..code.len() {
+    assert_eq!(Ok(()), subject.step());
+  }
+  assert_eq!(11, subject.register(3));
+  assert_eq!(11, subject.register(4));
+  assert_eq!(22, subject.register(5));
+  assert_eq!(11, subject.register(6));
+  assert_eq!(0, subject.register(15));
+}
+
+#[test]
+fn stack_access_faults() {
+  let run = |instruction: Instruction, sp: WordType| cpu(&[instruction], &[(15, sp)]).step();
+  assert_eq!(Err(CPUErr::StackUnderflow), run(Instruction::Peek(1, 0), 0));
+  assert_eq!(Err(CPUErr::StackUnderflow), run(Instruction::Poke(2, 1), 2));
+  assert_eq!(Ok(()), run(Instruction::Poke(1, 1), 2));
+  assert_eq!(Err(CPUErr::StackOverflow), run(Instruction::Peek(1, 0), 9));
+  assert_eq!(Err(CPUErr::StackUnderflow), run(Instruction::AdjustStack(-3), 2));
+  assert_eq!(Err(CPUErr::StackOverflow), run(Instruction::AdjustStack(7), 2));
+  assert_eq!(Ok(()), run(Instruction::AdjustStack(6), 2));
+}
+
+fn with_stack(code: &[Instruction], mode: StackMode) -> Program {
+  Program::new(code).size(48).registers(&[(1, 11), (2, 22)]).stack(mode)
+}
+
+#[test]
+fn stack_modes_agree() {
+  let code = [
+    Instruction::PushRegister(1),
+    Instruction::PushRegister(2),
+    Instruction::PushRegisters,
+    Instruction::Move(1, 2),
+    Instruction::PopRegisters,
+    Instruction::Peek(3, 1),
+    Instruction::PopRegister(4),
+    Instruction::PushRegisters,
+    Instruction::PushRegisters,
+  ];
+  let modes = [
+    StackMode::Separate(16),
+    StackMode::InMemory { base: 32, size: 16, growth: Growth::Up },
+    StackMode::InMemory { base: 47, size: 16, growth: Growth::Down },
+  ];
+  let mut results = Vec::new();
+  for mode in modes.iter() {
+    let mut subject = with_stack(&code, *mode).build();
+    for _ in 0..(code.len() - 1) {
+      assert_eq!(Ok(()), subject.step());
+    }
+    assert_eq!(Err(CPUErr::StackOverflow), subject.step());
+    results.push(subject.registers);
+  }
+  assert_eq!(results[0], results[1]);
+  assert_eq!(results[0], results[2]);
+  assert_eq!((11, 11, 22, 14), (results[0][1], results[0][3], results[0][4], results[0][15]));
+}
+
+#[test]
+fn stack_in_memory_is_visible() {
+  let code = [Instruction::PushRegister(1), Instruction::PushRegister(2), Instruction::Load(3, 5)];
+  let mut up = with_stack(&code, StackMode::InMemory { base: 32, size: 4, growth: Growth::Up })
+    .registers(&[(5, 33)])
+    .build();
+  let mut down = with_stack(&code, StackMode::InMemory { base: 32, size: 4, growth: Growth::Down })
+    .registers(&[(5, 31)])
+    .build();
+  for _ in 0..code.len() {
+    assert_eq!(Ok(()), up.step());
+    assert_eq!(Ok(()), down.step());
+  }
+  assert_eq!(22, up.register(3));
+  assert_eq!(22, down.register(3));
+  assert_eq!(Ok(11), down.memory.get(32));
+
+  // Corrupting the stack through memory shows up when popping.
+  let mut cpu = with_stack(&[Instruction::PushRegister(1), Instruction::Save(5, 2), Instruction::PopRegister(3)],
+    StackMode::InMemory { base: 40, size: 4, growth: Growth::Up }).registers(&[(5, 40)]).build();
+  for _ in 0..3 {
+    assert_eq!(Ok(()), cpu.step());
+  }
+  assert_eq!(22, cpu.register(3));
+}
+
+#[test]
+fn stack_outside_memory() {
+  let mut subject = with_stack(&[Instruction::PushRegister(1)], StackMode::InMemory { base: 60, size: 4, growth: Growth::Up }).build();
+  assert_eq!(Err(CPUErr::StackOverflow), subject.step());
+  assert_eq!(0, subject.register(15));
+}
+
+#[test]
+fn stack_regions_are_clamped() {
+  let clamped = |mode| with_stack(&[], mode).build().stack_mode();
+  assert_eq!(StackMode::InMemory { base: 40, size: 8, growth: Growth::Up },
+    clamped(StackMode::InMemory { base: 40, size: 16, growth: Growth::Up }));
+  assert_eq!(StackMode::InMemory { base: 3, size: 4, growth: Growth::Down },
+    clamped(StackMode::InMemory { base: 3, size: 16, growth: Growth::Down }));
+  assert_eq!(StackMode::InMemory { base: 60, size: 0, growth: Growth::Down },
+    clamped(StackMode::InMemory { base: 60, size: 4, growth: Growth::Down }));
+  assert_eq!(StackMode::Separate(16), clamped(StackMode::Separate(16)));
+
+  // Growing the stack past what fits faults, and so does popping with SP moved beyond it.
+  let mut subject = with_stack(&[Instruction::AdjustStack(9)], StackMode::InMemory { base: 40, size: 16, growth: Growth::Up }).build();
+  assert_eq!(Err(CPUErr::StackOverflow), subject.step());
+  for mode in &[StackMode::Separate(4), StackMode::InMemory { base: 40, size: 4, growth: Growth::Up }] {
+    for pop in &[Instruction::PopRegister(1), Instruction::PopRegisters] {
+      let mut subject = with_stack(&[*pop], *mode).registers(&[(15, 20)]).build();
+      assert_eq!(Err(CPUErr::StackOverflow), subject.step());
+    }
+  }
+}
+
+#[test]
+fn threaded_sees_pushes_over_code() {
+  // The push overwrites the Nop right after it with a BitNot of r5.
+  let code = [Instruction::PushRegister(1), Instruction::Nop];
+  let program = with_stack(&code, StackMode::InMemory { base: 1, size: 4, growth: Growth::Up });
+  let mut threaded = ThreadedCPU::new(program.registers(&[(1, Instruction::BitNot(5).into())]).build());
+  assert_eq!((2, Ok(())), threaded.run(2));
+  assert_eq!(0xFFFF, threaded.cpu().register(5));
+}
+
+const INTERRUPTS: WordType = 0x0040;
+const VECTORS: usize = 20;
+
+// An 80 word image with `code` at 0, `handler` at 10 and a vector table at 20 pointing `vectors` at
+// the handler, taking interrupts.
+fn trapping(code: &[Instruction], handler: &[Instruction], vectors: &[WordType]) -> Program {
+  Program::new(code).code(10, handler).vectors(VECTORS, vectors, 10).size(80).registers(&[(13, INTERRUPTS)])
+}
+
+// Steps both engines `steps` times and checks they end up in the same place.
+fn run_both(program: &Program, steps: usize) -> (Result<(), CPUErr>, Subject) {
+  let mut subject = program.build();
+  let mut res = Ok(());
+  for _ in 0..steps {
+    res = subject.step();
+    if res.is_err() {
+      break;
+    }
+  }
+
+  let mut threaded = ThreadedCPU::new(program.build());
+  let (_, threaded_res) = threaded.run(steps);
+  assert_eq!(res, threaded_res);
+  assert_eq!(subject.registers, threaded.cpu().registers);
+  (res, subject)
+}
+
+#[test]
+fn faults_go_to_handlers() {
+  // The handler skips the faulting instruction by bumping the pushed PC.
+  let handler = [
+    Instruction::Peek(4, 1),
+    Instruction::Add(4, 7),
+    Instruction::Poke(1, 4),
+    Instruction::ReturnFromInterrupt,
+  ];
+  let program = trapping(&[Instruction::Divide(1, 2), Instruction::Move(5, 7)], &handler, &[VEC_DIVIDE_BY_ZERO])
+    .registers(&[(7, 1)]);
+
+  let (res, subject) = run_both(&program, 1);
+  assert_eq!(Ok(()), res);
+  assert_eq!(10, subject.register(14));
+  assert_eq!(0, subject.register(13) & INTERRUPTS);
+  assert_eq!(2, subject.register(15));
+
+  let (res, subject) = run_both(&program, 6);
+  assert_eq!(Ok(()), res);
+  assert_eq!(1, subject.register(5));
+  assert_eq!(2, subject.register(14));
+  assert_eq!(INTERRUPTS, subject.register(13) & INTERRUPTS);
+  assert_eq!(0, subject.register(15));
+}
+
+#[test]
+fn faults_the_guest_cannot_take() {
+  let code = [Instruction::Divide(1, 2)];
+  let fault = Err(CPUErr::DivideByZero);
+
+  // Not enabled, no handler in the table, or nowhere to push the return address.
+  assert_eq!(fault, trapping(&code, &[], &[VEC_DIVIDE_BY_ZERO]).registers(&[(13, 0)]).build().step());
+  assert_eq!(fault, trapping(&code, &[], &[VEC_STACK_OVERFLOW]).build().step());
+  assert_eq!(fault, trapping(&code, &[], &[VEC_DIVIDE_BY_ZERO]).registers(&[(15, 7)]).build().step());
+
+  // Faults inside a handler go to the host.
+  let (res, subject) = run_both(&trapping(&code, &[Instruction::Divide(1, 2)], &[VEC_DIVIDE_BY_ZERO]), 2);
+  assert_eq!(fault, res);
+  assert_eq!(11, subject.register(14));
+}
+
+#[test]
+fn software_interrupts() {
+  let code = [Instruction::Interrupt(42), Instruction::Nop];
+  let handler = [Instruction::Move(5, 7), Instruction::ReturnFromInterrupt];
+  let program = trapping(&code, &handler, &[VEC_SOFTWARE + 42]).registers(&[(7, 9)]);
+  let (res, subject) = run_both(&program, 3);
+  assert_eq!(Ok(()), res);
+  assert_eq!(None, subject.interrupt());
+  assert_eq!(9, subject.register(5));
+  assert_eq!(1, subject.register(14));
+
+  // Claimed interrupts stay with the host.
+  let mut subject = program.build();
+  subject.claim(42);
+  assert_eq!(Ok(()), subject.step());
+  assert_eq!(Some(42), subject.interrupt());
+  assert_eq!(1, subject.register(14));
+}
+
+#[test]
+fn vector_base() {
+  let mut subject = cpu(&[Instruction::Interrupt(INT_VECTORS)], &[(0, 300)]);
+  subject.set_vectors(7);
+  assert_eq!(Ok(()), subject.step());
+  assert_eq!(7, subject.register(0));
+  assert_eq!(300, subject.vectors());
+  assert_eq!(None, subject.interrupt());
+
+  // Reading the base back leaves the table where it is.
+  let mut subject = cpu(&[Instruction::Interrupt(INT_VECTORS), Instruction::Interrupt(INT_VECTORS_READ)], &[(0, 300)]);
+  assert_eq!(Ok(()), subject.step());
+  subject.set_register(0, 0);
+  assert_eq!(Ok(()), subject.step());
+  assert_eq!((300, 300), (subject.register(0), subject.vectors()));
+
+  assert_eq!(Err(CPUErr::StackUnderflow), cpu(&[Instruction::ReturnFromInterrupt], &[(15, 1)]).step());
+}
+
+const TIMER: WordType = 0x0080;
+
+// Sets a timer of 4 and counts in r6, while the handler counts expiries in r5.
+fn timed(flags: WordType) -> Program {
+  trapping(&[Instruction::Interrupt(INT_TIMER)], &[Instruction::Add(5, 7), Instruction::ReturnFromInterrupt], &[VEC_TIMER])
+    .code(1, &[Instruction::Add(6, 7); 8])
+    .registers(&[(0, 4), (7, 1), (13, flags)])
+}
+
+#[test]
+fn timer_preempts() {
+  let (res, subject) = run_both(&timed(INTERRUPTS | TIMER), 5);
+  assert_eq!(Ok(()), res);
+  assert_eq!((0, 4, 10), (subject.register(5), subject.register(6), subject.register(14)));
+  assert_eq!(0, subject.register(0));
+  assert_eq!(4, subject.timer());
+
+  // Four instructions after the first expiry, two of them in the handler, it expires again.
+  let (_, subject) = run_both(&timed(INTERRUPTS | TIMER), 9);
+  assert_eq!((1, 6, 10), (subject.register(5), subject.register(6), subject.register(14)));
+}
+
+#[test]
+fn timer_masked() {
+  let (_, mut subject) = run_both(&timed(INTERRUPTS), 9);
+  assert_eq!((0, 8, 9), (subject.register(5), subject.register(6), subject.register(14)));
+
+  // The expiry has been waiting and goes off after the next instruction.
+  subject.set_register(13, INTERRUPTS | TIMER);
+  assert_eq!(Ok(()), subject.step());
+  assert_eq!(10, subject.register(14));
+
+  subject.set_timer(0);
+  subject.set_register(14, 1);
+  for _ in 0..8 {
+    assert_eq!(Ok(()), subject.step());
+  }
+  assert_eq!(9, subject.register(14));
+}
+
+// The first thread forks one that adds twice, leaves the sum at 20 and exits, then waits for it
+// and reads the sum back.
+fn forking(max_threads: usize) -> Program {
+  let code = [
+    Instruction::Interrupt(INT_FORK),
+    Instruction::Move(8, 0),
+    Instruction::Add(6, 7),
+    Instruction::Interrupt(INT_JOIN),
+    Instruction::Load(10, 9),
+    Instruction::JumpRelative(-1, 0),
+    Instruction::Add(5, 7),
+    Instruction::Add(5, 7),
+    Instruction::Save(9, 5),
+    Instruction::Interrupt(INT_KILL),
+  ];
+  Program::new(&code).size(32).registers(&[(0, 6), (7, 1), (9, 20)]).threads(max_threads)
+}
+
+#[test]
+fn threads_share_memory() {
+  let (res, subject) = run_both(&forking(2), 6);
+  assert_eq!(Ok(()), res);
+  assert_eq!(2, subject.threads());
+  assert_eq!(2, subject.thread());
+  assert_eq!(Ok(2), subject.memory.get(20));
+
+  // Waiting on the other thread takes a turn each time until it is gone.
+  let (res, subject) = run_both(&forking(2), 11);
+  assert_eq!(Ok(()), res);
+  assert_eq!(1, subject.threads());
+  assert_eq!(1, subject.thread());
+  assert_eq!((2, 1, 2, 0), (subject.register(8), subject.register(6), subject.register(10), subject.register(5)));
+  assert_eq!(5, subject.register(14));
+  assert!(!subject.is_running(2));
+}
+
+#[test]
+fn forked_stacks_follow_the_mode() {
+  // Both threads push onto their own stacks, the parent's at 24 and the child's from r1.
+  let code = [
+    Instruction::Interrupt(INT_FORK),
+    Instruction::PushRegister(7),
+    Instruction::JumpRelative(-1, 0),
+    Instruction::PushRegister(8),
+    Instruction::JumpRelative(-1, 0),
+  ];
+  let program = with_stack(&code, StackMode::InMemory { base: 24, size: 4, growth: Growth::Up })
+    .registers(&[(0, 3), (1, 28), (7, 7), (8, 8)])
+    .threads(2);
+  let (res, subject) = run_both(&program, 5);
+  assert_eq!(Ok(()), res);
+  assert_eq!((Ok(7), Ok(8)), (subject.memory.get(24), subject.memory.get(28)));
+  assert_eq!(1, subject.thread());
+  assert_eq!(StackMode::InMemory { base: 24, size: 4, growth: Growth::Up }, subject.stack_mode());
+
+  // The child's region is cut short like any other.
+  let mut subject = program.build();
+  assert_eq!(Some(2), subject.fork(3, 46));
+  subject.schedule();
+  assert_eq!(StackMode::InMemory { base: 46, size: 2, growth: Growth::Up }, subject.stack_mode());
+}
+
+#[test]
+fn thread_limits() {
+  let mut subject = forking(1).build();
+  assert_eq!(Ok(()), subject.step());
+  assert_eq!((0, 0), (subject.register(0), subject.register(13) & COMPARISON));
+  assert_eq!(1, subject.threads());
+
+  let mut subject = forking(2).build();
+  assert_eq!(Some(2), subject.fork(6, 0));
+  assert_eq!(None, subject.fork(6, 0));
+  assert!(!subject.kill(7));
+  assert!(subject.kill(2));
+  assert!(!subject.kill(0));
+  assert_eq!(Some(3), subject.fork(6, 0));
+  assert!(subject.kill(0));
+  assert!(!subject.is_running(1));
+  assert_eq!(Ok(()), subject.step());
+  assert_eq!(3, subject.thread());
+  assert_eq!(1, subject.threads());
+}
+
+#[test]
+fn atomic_updates() {
+  let code = [
+    Instruction::CompareAndSwap(1, 2),
+    Instruction::Move(3, 13),
+    Instruction::CompareAndSwap(1, 2),
+    Instruction::FetchAdd(1, 2),
+    Instruction::Swap(1, 4),
+    Instruction::FetchAdd(5, 2),
+  ];
+  let program = Program::new(&code).size(16).registers(&[(1, 8), (2, 5), (4, 7), (5, 0xFFFF)]);
+
+  let (res, subject) = run_both(&program, code.len());
+  assert_eq!(Err(CPUErr::MemoryErr(MemoryErr::PointerOutOfRange(16, 0xFFFF))), res);
+  assert_eq!(COMPARISON, subject.register(3) & COMPARISON);
+  assert_eq!((5, 0), (subject.register(0), subject.register(13) & COMPARISON));
+  assert_eq!((5, 10), (subject.register(2), subject.register(4)));
+  assert_eq!(Ok(7), subject.memory.get(8));
+
+  // Updates past main memory land in an attached region.
+  let region = Arc::new(RwLock::new(Memory::new(2)));
+  let mut subject = program.build();
+  subject.attach_shared(region.clone());
+  region.write().unwrap().set(1, 40).unwrap();
+  for _ in 0..6 {
+    assert_eq!(Ok(()), subject.step());
+  }
+  assert_eq!(Ok(45), region.read().unwrap().get(1));
+  assert_eq!(40, subject.register(2));
+}
+
+// A 32 word image with `code` at 0 and 1 to 6 at 20.
+fn blocks(code: &[Instruction], registers: &[(usize, WordType)]) -> Program {
+  Program::new(code).words(20, &[1, 2, 3, 4, 5, 6]).size(32).registers(registers)
+}
+
+#[test]
+fn block_operations() {
+  let code = [
+    Instruction::CopyBlock(1, 2),
+    Instruction::CopyBlock(2, 3),
+    Instruction::CompareBlock(2, 3),
+    Instruction::Move(5, 13),
+    Instruction::FillBlock(4, 6),
+    Instruction::CompareBlock(7, 4),
+    Instruction::Move(8, 13),
+    Instruction::CompareBlock(4, 4),
+  ];
+  let registers = [(0, 4), (1, 22), (2, 20), (3, 21), (4, 28), (6, 9), (7, 26)];
+  let (res, subject) = run_both(&blocks(&code, &registers), code.len());
+  assert_eq!(Ok(()), res);
+
+  // Overlapping copies in either direction move the source as it was.
+  assert_eq!(Ok(&[2, 1, 2, 3, 3, 4, 0, 0, 9, 9, 9, 9][..]), subject.memory.get_range(20, 12));
+  assert_eq!(0, subject.register(5) & (COMPARISON | CARRY));
+  assert_eq!(CARRY, subject.register(8) & (COMPARISON | CARRY));
+  assert_eq!(COMPARISON, subject.register(13) & (COMPARISON | CARRY));
+}
+
+#[test]
+fn block_faults() {
+  let mut subject = blocks(&[Instruction::CopyBlock(1, 2)], &[(0, 4), (1, 0), (2, 30)]).build();
+  assert_eq!(Err(CPUErr::MemoryErr(MemoryErr::PointerRangeOverflow(32, 30, 4))), subject.step());
+
+  // Nothing is written unless every word can be.
+  let mut subject = blocks(&[Instruction::FillBlock(1, 2)], &[(0, 3), (1, 30), (2, 9)]).build();
+  assert_eq!(Err(CPUErr::MemoryErr(MemoryErr::PointerRangeOverflow(32, 30, 33))), subject.step());
+  assert_eq!(Ok(&[0, 0][..]), subject.memory.get_range(30, 2));
+
+  let mut subject = blocks(&[Instruction::FillBlock(1, 2)], &[(0, 2), (1, 31), (2, 9)]).build();
+  assert_eq!(32, subject.map_window(vec![7, 8], 10));
+  assert!(subject.step().is_err());
+  assert_eq!(Ok(0), subject.memory.get(31));
+
+  let mut subject = blocks(&[Instruction::FillBlock(1, 2)], &[(0, 2), (1, 0xFFFF), (2, 9)]).build();
+  assert!(subject.step().is_err());
+
+  // The read-only window can be copied from, and an attached region written to.
+  let region = Arc::new(RwLock::new(Memory::new(4)));
+  let mut subject = blocks(
+    &[Instruction::CopyBlock(1, 2), Instruction::CopyBlock(3, 1)],
+    &[(0, 3), (1, 29), (2, 31), (3, 0xFFFD)]
+  ).build();
+  subject.map_window(vec![7, 8], 10);
+  assert_eq!(0xFFFC, subject.attach_shared(region.clone()));
+  assert_eq!(Ok(()), subject.step());
+  assert_eq!(Ok(&[0, 7, 8][..]), subject.memory.get_range(29, 3));
+  assert_eq!(Ok(()), subject.step());
+  assert_eq!(&[0, 0, 7, 8][..], region.read().unwrap().get_range(0, 4).unwrap());
+}
+
+#[test]
+fn threaded_sees_block_writes_over_code() {
+  // The fill turns the Add it loops back to into a Nop.
+  let code = [Instruction::Add(3, 4), Instruction::FillBlock(1, 2), Instruction::JumpRelative(-3, 0)];
+  let (res, subject) = run_both(&blocks(&code, &[(0, 1), (4, 1)]), 9);
+  assert_eq!(Ok(()), res);
+  assert_eq!(1, subject.register(3));
+}
@@ -0,0 +1,100 @@
+use crate::cpu::WordType;
+use crate::crossover::{
+  Crossover as Subject,
+  ChildPolicy,
+  Entry,
+  StackSize,
+};
+use crate::instruction::Instruction;
+use crate::memory::Memory;
+
+fn image(words: &[WordType]) -> Memory {
+  let mut memory = Memory::new(words.len() as WordType);
+  assert_eq!(Ok(()), memory.set_range(0, words));
+  memory
+}
+
+#[test]
+fn single_point() {
+  let first = image(&[1; 8]);
+  let second = image(&[2; 12]);
+  let mut subject = Subject::new(3);
+
+  for _ in 0..50 {
+    let child = subject.single_point(&first, &second);
+    let cut = child.raw().iter().take_while(|word| **word == 1).count();
+    assert_eq!(12, child.len());
+    assert!(cut <= 8);
+    assert!(child.raw()[cut..].iter().all(|word| *word == 2));
+  }
+}
+
+#[test]
+fn two_point() {
+  let first = image(&[1; 12]);
+  let second = image(&[2; 8]);
+  let mut subject = Subject::new(5);
+
+  for _ in 0..50 {
+    let child = subject.two_point(&first, &second);
+    assert_eq!(12, child.len());
+    let spliced: Vec<usize> = (0..12).filter(|pos| child.raw()[*pos] == 2).collect();
+    if let (Some(start), Some(end)) = (spliced.first(), spliced.last()) {
+      assert!(*end < 8);
+      assert_eq!(end - start + 1, spliced.len());
+    }
+  }
+}
+
+#[test]
+fn uniform() {
+  let first = image(&[1; 64]);
+  let second = image(&[2; 32]);
+  let mut subject = Subject::new(9);
+
+  let child = subject.uniform(&first, &second);
+  assert_eq!(64, child.len());
+  assert!(child.raw()[..32].contains(&1));
+  assert!(child.raw()[..32].contains(&2));
+  assert!(child.raw()[32..].iter().all(|word| *word == 1));
+}
+
+#[test]
+fn aligned_keeps_relative_targets() {
+  let jump = WordType::from(Instruction::JumpRelative(-4, 0));
+  let load = WordType::from(Instruction::LoadRelative(2));
+  let first = image(&[1, 1, 1, 1, jump, 1, load, 1, 1]);
+  let second = image(&[2; 9]);
+  let mut subject = Subject::new(11);
+
+  for _ in 0..50 {
+    let child = subject.aligned(&first, &second);
+    let cut = child.raw().iter().take_while(|word| **word != 2).count();
+    assert!(cut <= 1 || cut == 5 || cut == 6 || cut == 9, "cut at {}", cut);
+  }
+
+  // Every word reaches the next, so a genome this size only has its two ends left to cut at.
+  let load = WordType::from(Instruction::LoadRelative(1));
+  let first = image(&vec![load; 60_000]);
+  let second = image(&vec![2; 60_000]);
+  for _ in 0..4 {
+    let child = subject.aligned(&first, &second);
+    let cut = child.raw().iter().take_while(|word| **word != 2).count();
+    assert!(cut == 0 || cut == 60_000, "cut at {}", cut);
+  }
+}
+
+#[test]
+fn child() {
+  let mut subject = Subject::new(1);
+  let policy = ChildPolicy { entry: Entry::Fixed(3), stack_size: StackSize::PerGenome(4) };
+
+  let cpu = subject.child(&policy, image(&[0; 32]));
+  assert_eq!(3, cpu.registers[14]);
+  assert_eq!(8, cpu.stack.len());
+
+  let policy = ChildPolicy { entry: Entry::Random, stack_size: StackSize::Fixed(5) };
+  let cpu = subject.child(&policy, image(&[0; 32]));
+  assert!(cpu.registers[14] < 32);
+  assert_eq!(5, cpu.stack.len());
+}
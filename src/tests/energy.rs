@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use crate::cpu::{CPU, CPUErr, WordType};
+use crate::energy::CostTable as Subject;
+use crate::instruction::Instruction;
+use crate::instruction::codes::*;
+use crate::memory::Memory;
+use crate::threaded::ThreadedCPU;
+
+fn program() -> Memory {
+  let code = [
+    Instruction::Add(1, 2),
+    Instruction::Multiply(1, 2),
+    Instruction::Load(3, 1),
+    Instruction::JumpRelative(-4, 0),
+  ];
+  let words: Vec<WordType> = code.iter().map(WordType::from).collect();
+  Memory::from(&words[..])
+}
+
+#[test]
+fn default_costs() {
+  let subject = Subject::default();
+
+  assert_eq!(1, subject.cost(ADD));
+  assert_eq!(2, subject.cost(LD));
+  assert_eq!(4, subject.cost(MUL));
+  assert_eq!(10, subject.cost(INT));
+}
+
+#[test]
+fn charges_per_instruction() {
+  let mut cpu = CPU::new(program(), 0, 8);
+  cpu.meter(10, Arc::new(Subject::default()));
+
+  assert_eq!(Ok(()), cpu.step());
+  assert_eq!(Some(9), cpu.energy());
+  assert_eq!(Ok(()), cpu.step());
+  assert_eq!(Some(5), cpu.energy());
+  assert_eq!(Ok(()), cpu.step());
+  assert_eq!(Some(3), cpu.energy());
+  assert_eq!(Ok(()), cpu.step());
+  assert_eq!(Some(2), cpu.energy());
+}
+
+#[test]
+fn halts_when_out_of_energy() {
+  let mut cpu = CPU::new(program(), 0, 8);
+  cpu.meter(3, Arc::new(Subject::default()));
+
+  assert_eq!(Ok(()), cpu.step());
+  assert_eq!(Err(CPUErr::OutOfEnergy), cpu.step());
+  assert_eq!(Err(CPUErr::OutOfEnergy), cpu.step());
+  assert_eq!(1, cpu.register(14));
+  assert_eq!(Some(2), cpu.energy());
+
+  cpu.grant(2);
+  assert_eq!(Ok(()), cpu.step());
+  assert_eq!(2, cpu.register(14));
+  assert_eq!(Some(0), cpu.energy());
+}
+
+#[test]
+fn unmetered() {
+  let mut cpu = CPU::new(program(), 0, 8);
+
+  assert_eq!(Ok(()), cpu.step());
+  assert_eq!(None, cpu.energy());
+  assert_eq!(0, cpu.take_energy(5));
+}
+
+#[test]
+fn take_energy() {
+  let mut cpu = CPU::new(program(), 0, 8);
+  cpu.meter(10, Arc::new(Subject::default()));
+
+  assert_eq!(4, cpu.take_energy(4));
+  assert_eq!(6, cpu.take_energy(40));
+  assert_eq!(Some(0), cpu.energy());
+}
+
+#[test]
+fn threaded_engine_charges_the_same() {
+  let mut expected = CPU::new(program(), 0, 8);
+  expected.meter(100, Arc::new(Subject::default()));
+  let mut subject = CPU::new(program(), 0, 8);
+  subject.meter(100, Arc::new(Subject::default()));
+  let mut subject = ThreadedCPU::new(subject);
+
+  let steps = (0..).map(|_| expected.step()).take_while(|res| res.is_ok()).count();
+  assert_eq!((steps + 1, Err(CPUErr::OutOfEnergy)), subject.run(1000));
+  assert_eq!(expected.energy(), subject.cpu().energy());
+  assert_eq!(expected.register(14), subject.cpu().register(14));
+}
+
+#[test]
+fn charges_per_word() {
+  let code = [Instruction::FillBlock(1, 2), Instruction::CompareBlock(1, 1)];
+  let mut words: Vec<WordType> = code.iter().map(WordType::from).collect();
+  words.resize(16, 0);
+  let mut costs = Subject::default();
+  costs.set_word_cost(3);
+  let mut cpu = CPU::new(Memory::from(&words[..]), 0, 8);
+  cpu.meter(39, Arc::new(costs));
+  cpu.set_register(0, 6);
+  cpu.set_register(1, 8);
+
+  assert_eq!(1, Subject::default().word_cost());
+  assert_eq!(Ok(()), cpu.step());
+  assert_eq!(Some(39 - 2 - 6 * 3), cpu.energy());
+  assert_eq!(Err(CPUErr::OutOfEnergy), cpu.step());
+  assert_eq!(1, cpu.register(14));
+}
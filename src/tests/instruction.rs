@@ -1,5 +1,6 @@
 use crate::instruction::Instruction as Subject;
 use crate::cpu::WordType;
+use crate::instruction::codes::{name, EXT, MEM, NOP};
 #[test]
 fn from_word_type() {
   assert_eq!(Subject::Nop, Subject::from(0b0000000000000000));
@@ -31,6 +32,24 @@ fn from_word_type() {
   assert_eq!(Subject::BitNor(1, 1), Subject::from(0b0000001000111011));
   assert_eq!(Subject::LoadRelative(-2), Subject::from(0b1111111000111100));
   assert_eq!(Subject::JumpRelative(-2, 1), Subject::from(0b1111111000111101));
+  assert_eq!(Subject::JumpRelative(-2, 7), Subject::from(0b1111111011111101));
+  assert_eq!(Subject::Jump(1, 8), Subject::from(0b0001000000110010));
+  assert_eq!(Subject::SignedGreaterThan(1, 2), Subject::from(0b0000010000110100));
+  assert_eq!(Subject::SignedLessThan(1, 2), Subject::from(0b0010010000110100));
+  assert_eq!(Subject::ArithmeticShiftRight(1, 2), Subject::from(0b0100010000110100));
+  assert_eq!(Subject::SignedDivide(1, 2), Subject::from(0b0110010000110100));
+  assert_eq!(Subject::CompareAndSwap(1, 2), Subject::from(0b1000010000110100));
+  assert_eq!(Subject::FetchAdd(1, 2), Subject::from(0b1010010000110100));
+  assert_eq!(Subject::Swap(1, 2), Subject::from(0b1100010000110100));
+  assert_eq!(Subject::ReturnFromInterrupt, Subject::from(0b1110000000010100));
+  assert_eq!(Subject::LoadOffset(1, 2), Subject::from(0b0000010000111111));
+  assert_eq!(Subject::SaveOffset(1, 2), Subject::from(0b0010010000111111));
+  assert_eq!(Subject::Peek(1, 2), Subject::from(0b0100010000111111));
+  assert_eq!(Subject::Poke(1, 2), Subject::from(0b0110010000111111));
+  assert_eq!(Subject::AdjustStack(-2), Subject::from(0b1001111111011111));
+  assert_eq!(Subject::CopyBlock(1, 2), Subject::from(0b1010010000111111));
+  assert_eq!(Subject::FillBlock(1, 2), Subject::from(0b1100010000111111));
+  assert_eq!(Subject::CompareBlock(1, 2), Subject::from(0b1110010000111111));
 }
 
 #[test]
@@ -64,5 +83,31 @@ fn from_instruction() {
   assert_eq!(0b0000001000111011, WordType::from(Subject::BitNor(1, 1)));
   assert_eq!(0b1111111000011100, WordType::from(Subject::LoadRelative(-2)));
   assert_eq!(0b1111111000111101, WordType::from(Subject::JumpRelative(-2, 1)));
+  assert_eq!(0b1111111011111101, WordType::from(Subject::JumpRelative(-2, 7)));
+  assert_eq!(0b0001000000110010, WordType::from(Subject::Jump(1, 8)));
   assert_eq!(0b0000000100011110, WordType::from(Subject::SaveRelative(1)));
+  assert_eq!(0b0000010000110100, WordType::from(Subject::SignedGreaterThan(1, 2)));
+  assert_eq!(0b0010010000110100, WordType::from(Subject::SignedLessThan(1, 2)));
+  assert_eq!(0b0100010000110100, WordType::from(Subject::ArithmeticShiftRight(1, 2)));
+  assert_eq!(0b0110010000110100, WordType::from(Subject::SignedDivide(1, 2)));
+  assert_eq!(0b1000010000110100, WordType::from(Subject::CompareAndSwap(1, 2)));
+  assert_eq!(0b1010010000110100, WordType::from(Subject::FetchAdd(1, 2)));
+  assert_eq!(0b1100010000110100, WordType::from(Subject::Swap(1, 2)));
+  assert_eq!(0b1110000000010100, WordType::from(Subject::ReturnFromInterrupt));
+  assert_eq!(0b0000010000111111, WordType::from(Subject::LoadOffset(1, 2)));
+  assert_eq!(0b0010010000111111, WordType::from(Subject::SaveOffset(1, 2)));
+  assert_eq!(0b0100010000111111, WordType::from(Subject::Peek(1, 2)));
+  assert_eq!(0b0110010000111111, WordType::from(Subject::Poke(1, 2)));
+  assert_eq!(0b1001111111011111, WordType::from(Subject::AdjustStack(-2)));
+  assert_eq!(0b1010010000111111, WordType::from(Subject::CopyBlock(1, 2)));
+  assert_eq!(0b1100010000111111, WordType::from(Subject::FillBlock(1, 2)));
+  assert_eq!(0b1110010000111111, WordType::from(Subject::CompareBlock(1, 2)));
+}
+
+#[test]
+fn names() {
+  assert_eq!("NOP", name(NOP));
+  assert_eq!("EXT", name(EXT));
+  assert_eq!("MEM", name(MEM));
+  assert_eq!("INVALID", name(MEM + 1));
 }
\ No newline at end of file
@@ -0,0 +1,52 @@
+use crate::lineage::Lineage as Subject;
+use crate::lineage::Record;
+use crate::mutation::Mutation;
+
+fn record(id: u64, parents: &[u64], generation: u64) -> Record {
+  Record { id, parents: parents.to_vec(), generation, mutations: Vec::new() }
+}
+
+fn lineage() -> Subject {
+  let mut subject = Subject::new();
+  subject.record(record(0, &[], 0));
+  subject.record(record(1, &[], 0));
+  subject.record(record(2, &[0], 1));
+  subject.record(Record { mutations: vec![Mutation::Deletion { pos: 3 }], ..record(3, &[0, 1], 1) });
+  subject.record(record(5, &[2], 2));
+  subject
+}
+
+#[test]
+fn get() {
+  let subject = lineage();
+
+  assert_eq!(5, subject.len());
+  assert_eq!(Some(&record(2, &[0], 1)), subject.get(2));
+  assert_eq!(vec![Mutation::Deletion { pos: 3 }], subject.get(3).unwrap().mutations);
+  assert_eq!(None, subject.get(4));
+  assert_eq!(None, subject.get(6));
+}
+
+#[test]
+fn newick() {
+  assert_eq!("((5)2,3)0;\n1;\n", lineage().newick());
+  assert_eq!("", Subject::new().newick());
+}
+
+#[test]
+fn edge_list() {
+  assert_eq!("parent,child,generation\n0,2,1\n0,3,1\n1,3,1\n2,5,2\n", lineage().edge_list());
+}
+
+#[test]
+fn deep_newick() {
+  let mut subject = Subject::new();
+  subject.record(record(0, &[], 0));
+  for id in 1..100_000 {
+    subject.record(record(id, &[id - 1], id));
+  }
+
+  let newick = subject.newick();
+  assert!(newick.starts_with("(((("));
+  assert!(newick.ends_with(")1)0;\n"));
+}
@@ -1,4 +1,5 @@
 use crate::cpu::WordType;
+use crate::instruction::Instruction;
 use crate::memory::{
   Memory as Subject,
   MemoryErr
@@ -41,6 +42,13 @@ fn get_range() {
   assert_eq!(Ok(&data[0..]), subject.get_range(0, 4));
 }
 
+#[test]
+fn words() {
+  let data: [u16; 4] = [12, 13, 24, 33];
+  assert_eq!(&data[..], Subject::from(&data[..]).words());
+  assert!(Subject::new(0).words().is_empty());
+}
+
 #[test]
 fn get_range_overflow() {
   let size = 8;
@@ -93,4 +101,63 @@ fn set_range_overflow() {
   let data: [u16; 4] = [12, 13, 24, 33];
   let mut subject = Subject::new(size);
   assert_eq!(Err(MemoryErr::PointerRangeOverflow(size, pos, pos + (data.len() as WordType))), subject.set_range(pos, &data));
+}
+
+#[test]
+fn set_range_offset() {
+  let data: [u16; 4] = [12, 13, 24, 33];
+  let mut subject = Subject::new(8);
+  assert_eq!(Ok(()), subject.set_range(2, &data));
+
+  assert_eq!([0, 0, 12, 13, 24, 33, 0, 0], subject.raw()[0..]);
+}
+
+#[test]
+fn decode_cached() {
+  let mut subject = Subject::new(8);
+  subject.enable_decode_cache();
+  subject.raw_mut()[1] = WordType::from(Instruction::Add(1, 2));
+
+  assert_eq!(Ok(Instruction::Add(1, 2)), subject.decode(1));
+  assert_eq!(Ok(Instruction::Add(1, 2)), subject.decode(1));
+}
+
+#[test]
+fn decode_invalidated_by_set() {
+  let mut subject = Subject::new(8);
+  subject.enable_decode_cache();
+  assert_eq!(Ok(()), subject.set(1, WordType::from(Instruction::Add(1, 2))));
+  assert_eq!(Ok(Instruction::Add(1, 2)), subject.decode(1));
+
+  assert_eq!(Ok(()), subject.set(1, WordType::from(Instruction::Subtract(1, 2))));
+  assert_eq!(Ok(Instruction::Subtract(1, 2)), subject.decode(1));
+}
+
+#[test]
+fn decode_invalidated_by_set_range() {
+  let mut subject = Subject::new(8);
+  subject.enable_decode_cache();
+  assert_eq!(Ok(Instruction::Nop), subject.decode(3));
+  assert_eq!(Ok(Instruction::Nop), subject.decode(4));
+
+  let data = [WordType::from(Instruction::BitNot(3)), WordType::from(Instruction::Not(4))];
+  assert_eq!(Ok(()), subject.set_range(3, &data));
+  assert_eq!(Ok(Instruction::BitNot(3)), subject.decode(3));
+  assert_eq!(Ok(Instruction::Not(4)), subject.decode(4));
+}
+
+#[test]
+fn decode_out_of_range() {
+  let size = 8;
+  let pos = 9;
+  let mut subject = Subject::new(size);
+  subject.enable_decode_cache();
+
+  assert_eq!(Err(MemoryErr::PointerOutOfRange(size, pos)), subject.decode(pos));
+}
+
+#[test]
+fn is_empty() {
+  assert!(Subject::new(0).is_empty());
+  assert!(!Subject::new(1).is_empty());
 }
\ No newline at end of file
@@ -1,2 +1,16 @@
 mod instruction;
-mod memory;
\ No newline at end of file
+mod memory;
+mod atomic_memory;
+mod threaded;
+mod scheduler;
+mod processor;
+mod mutation;
+mod crossover;
+mod population;
+mod energy;
+mod world;
+mod lineage;
+mod species;
+mod rng;
+mod pair;
+mod cpu;
\ No newline at end of file
@@ -0,0 +1,139 @@
+use crate::cpu::WordType;
+use crate::instruction::Instruction;
+use crate::instruction::codes::OPCODES;
+use crate::memory::Memory;
+use crate::mutation::{
+  Mutation as Subject,
+  Mutator,
+};
+
+fn image(code: &[Instruction]) -> Memory {
+  let mut memory = Memory::new(code.len() as WordType);
+  for (pos, instruction) in code.iter().enumerate() {
+    assert_eq!(Ok(()), memory.set(pos as WordType, WordType::from(instruction)));
+  }
+  memory
+}
+
+fn decoded(memory: &Memory) -> Vec<Instruction> {
+  memory.raw().iter().map(Instruction::from).collect()
+}
+
+#[test]
+fn point() {
+  let mut memory = image(&[Instruction::Nop, Instruction::Add(1, 2)]);
+  assert_eq!(Ok(()), Subject::Point { pos: 1, mask: 0b0000_0010_0000_0000 }.apply(&mut memory));
+
+  assert_eq!(Instruction::Add(1, 3), Instruction::from(memory.raw()[1]));
+}
+
+#[test]
+fn opcode() {
+  let mut memory = image(&[Instruction::Add(1, 2)]);
+  assert_eq!(Ok(()), Subject::Opcode { pos: 0, opcode: crate::instruction::codes::SUB }.apply(&mut memory));
+
+  assert_eq!(Instruction::Subtract(1, 2), Instruction::from(memory.raw()[0]));
+}
+
+#[test]
+fn operand() {
+  let mut memory = image(&[Instruction::Add(1, 2)]);
+  assert_eq!(Ok(()), Subject::Operand { pos: 0, slot: 1, register: 7 }.apply(&mut memory));
+
+  assert_eq!(Instruction::Add(1, 7), Instruction::from(memory.raw()[0]));
+}
+
+#[test]
+fn insertion_relocates_relative_offsets() {
+  let mut memory = image(&[
+    Instruction::LoadRelative(4),
+    Instruction::Nop,
+    Instruction::JumpRelative(-3, 1),
+    Instruction::Nop,
+    Instruction::Nop,
+    Instruction::Nop,
+  ]);
+  assert_eq!(Ok(()), Subject::Insertion { pos: 1, value: WordType::from(Instruction::BitNot(2)) }.apply(&mut memory));
+
+  assert_eq!(vec![
+    Instruction::LoadRelative(5),
+    Instruction::BitNot(2),
+    Instruction::Nop,
+    Instruction::JumpRelative(-4, 1),
+    Instruction::Nop,
+    Instruction::Nop,
+  ], decoded(&memory));
+}
+
+#[test]
+fn deletion_relocates_relative_offsets() {
+  let mut memory = image(&[
+    Instruction::LoadRelative(4),
+    Instruction::BitNot(2),
+    Instruction::Nop,
+    Instruction::SaveRelative(-3),
+    Instruction::JumpRelative(-5, 0),
+  ]);
+  assert_eq!(Ok(()), Subject::Deletion { pos: 1 }.apply(&mut memory));
+
+  assert_eq!(vec![
+    Instruction::LoadRelative(3),
+    Instruction::Nop,
+    Instruction::SaveRelative(-2),
+    Instruction::JumpRelative(-4, 0),
+    Instruction::Nop,
+  ], decoded(&memory));
+}
+
+#[test]
+fn duplication() {
+  let mut memory = image(&[
+    Instruction::Add(1, 2),
+    Instruction::Subtract(3, 4),
+    Instruction::JumpRelative(-3, 0),
+    Instruction::Nop,
+    Instruction::Nop,
+  ]);
+  assert_eq!(Ok(()), Subject::Duplication { pos: 0, count: 2 }.apply(&mut memory));
+
+  assert_eq!(vec![
+    Instruction::Add(1, 2),
+    Instruction::Subtract(3, 4),
+    Instruction::Add(1, 2),
+    Instruction::Subtract(3, 4),
+    Instruction::JumpRelative(-5, 0),
+  ], decoded(&memory));
+}
+
+#[test]
+fn out_of_range() {
+  let mut memory = image(&[Instruction::Nop]);
+
+  assert!(Subject::Deletion { pos: 1 }.apply(&mut memory).is_err());
+  assert!(Subject::Insertion { pos: 1, value: 0 }.apply(&mut memory).is_err());
+}
+
+#[test]
+fn opcodes_stay_valid() {
+  let mut mutator = Mutator::new(7);
+  let memory = image(&[Instruction::Nop; 16]);
+  for _ in 0..200 {
+    match mutator.opcode(&memory) {
+      Some(Subject::Opcode { opcode, .. }) => assert!(OPCODES.contains(&opcode)),
+      other => panic!("unexpected mutation {:?}", other)
+    }
+  }
+}
+
+#[test]
+fn reproducible() {
+  let run = |seed| {
+    let mut mutator = Mutator::new(seed);
+    let mut memory = image(&[Instruction::Add(1, 2); 32]);
+    let mutations: Vec<Option<Subject>> = (0..100).map(|_| mutator.mutate(&mut memory)).collect();
+    (mutations, memory.raw().to_vec())
+  };
+
+  assert_eq!(run(42), run(42));
+  assert_ne!(run(42), run(43));
+}
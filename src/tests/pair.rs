@@ -0,0 +1,109 @@
+use std::sync::{Arc, RwLock};
+
+use crate::cpu::*;
+use crate::instruction::Instruction;
+use crate::memory::{Memory, MemoryErr};
+use crate::pair::PairMachine as Subject;
+use crate::pair::Role;
+use crate::threaded::ThreadedCPU;
+
+fn cpu(code: &[Instruction]) -> CPU {
+  let words: Vec<WordType> = code.iter().map(WordType::from).collect();
+  CPU::new(Memory::from(&words[..]), 0, 8)
+}
+
+fn spin() -> CPU {
+  cpu(&[Instruction::JumpRelative(-1, 0)])
+}
+
+#[test]
+fn shares_region() {
+  // The region is 4 words long, so it starts at 0xFFFC. r1 points one word into it.
+  let mut provider = cpu(&[Instruction::Save(1, 2), Instruction::JumpRelative(-1, 0)]);
+  provider.set_register(1, 0xFFFD);
+  provider.set_register(2, 42);
+  let mut selector = cpu(&[Instruction::Nop, Instruction::Load(3, 1), Instruction::JumpRelative(-1, 0)]);
+  selector.set_register(1, 0xFFFD);
+  let mut subject = Subject::new(provider, selector, 4);
+
+  assert_eq!(Ok(()), subject.step());
+  assert_eq!(Ok(()), subject.step());
+  assert_eq!(42, subject.cpu(Role::Selector).register(3));
+  assert_eq!(Ok(42), subject.shared().read().unwrap().get(1));
+}
+
+#[test]
+fn region_is_bounded() {
+  let mut provider = cpu(&[Instruction::Save(1, 2)]);
+  provider.set_register(1, 0xFFFB);
+  let mut subject = Subject::new(provider, spin(), 4);
+
+  assert_eq!(Err((Role::Provider, CPUErr::MemoryErr(MemoryErr::PointerOutOfRange(1, 0xFFFB)))), subject.step());
+}
+
+#[test]
+fn passes_messages() {
+  let mut provider = cpu(&[Instruction::Interrupt(INT_PAIR_SEND), Instruction::JumpRelative(-1, 0)]);
+  provider.set_register(0, 7);
+  let selector = cpu(&[
+    Instruction::Interrupt(INT_PAIR_RECEIVE),
+    Instruction::Move(1, 0),
+    Instruction::Interrupt(INT_PAIR_RECEIVE),
+    Instruction::JumpRelative(-1, 0),
+  ]);
+  let mut subject = Subject::new(provider, selector, 0);
+
+  assert_eq!(Ok(()), subject.step());
+  let selector = subject.cpu(Role::Selector);
+  assert_eq!(7, selector.register(0));
+  assert_eq!(0x0002, selector.register(13) & 0x0002);
+  assert_eq!(0x0002, subject.cpu(Role::Provider).register(13) & 0x0002);
+
+  assert_eq!(Ok(()), subject.step());
+  assert_eq!(Ok(()), subject.step());
+  let selector = subject.cpu(Role::Selector);
+  assert_eq!(7, selector.register(1));
+  assert_eq!(0, selector.register(13) & 0x0002);
+  assert_eq!(None, subject.take_interrupt());
+}
+
+#[test]
+fn dies_together() {
+  let selector = cpu(&[Instruction::Nop, Instruction::PopRegister(0)]);
+  let mut subject = Subject::new(spin(), selector, 0);
+
+  assert_eq!(Ok(()), subject.step());
+  assert_eq!(Err((Role::Selector, CPUErr::StackUnderflow)), subject.step());
+  assert!(!subject.is_alive());
+  assert_eq!(Some(&(Role::Selector, CPUErr::StackUnderflow)), subject.fault());
+
+  let pc = subject.cpu(Role::Provider).register(14);
+  assert_eq!(Err((Role::Selector, CPUErr::StackUnderflow)), subject.step());
+  assert_eq!(pc, subject.cpu(Role::Provider).register(14));
+}
+
+#[test]
+fn leaves_host_interrupts() {
+  let provider = cpu(&[Instruction::Interrupt(INT_REPRODUCE), Instruction::JumpRelative(-1, 0)]);
+  let selector = cpu(&[Instruction::Interrupt(INT_INSPECT), Instruction::JumpRelative(-1, 0)]);
+  let mut subject = Subject::new(provider, selector, 0);
+
+  assert_eq!(Ok(()), subject.step());
+  assert_eq!(Some((Role::Provider, INT_REPRODUCE)), subject.take_interrupt());
+  assert_eq!(Some((Role::Selector, INT_INSPECT)), subject.take_interrupt());
+  assert_eq!(None, subject.take_interrupt());
+}
+
+#[test]
+fn threaded_engine_sees_region() {
+  let region = Arc::new(RwLock::new(Memory::new(2)));
+  let mut cpu = cpu(&[Instruction::Save(1, 2), Instruction::Load(3, 1), Instruction::JumpRelative(-1, 0)]);
+  assert_eq!(0xFFFE, cpu.attach_shared(region.clone()));
+  cpu.set_register(1, 0xFFFF);
+  cpu.set_register(2, 9);
+  let mut subject = ThreadedCPU::new(cpu);
+
+  assert_eq!((3, Ok(())), subject.run(3));
+  assert_eq!(Ok(9), region.read().unwrap().get(1));
+  assert_eq!(9, subject.cpu().register(3));
+}
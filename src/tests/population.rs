@@ -0,0 +1,384 @@
+use std::sync::Arc;
+
+use crate::cpu::{CPU, CPUErr, WordType, INT_DECIDE, INT_INSPECT, INT_REPRODUCE};
+use crate::energy::CostTable;
+use crate::instruction::Instruction;
+use crate::memory::{Memory, MemoryErr};
+use crate::pair::{PairMachine, Role};
+use crate::population::*;
+use crate::population::Population as Subject;
+
+// Copies its whole image into a child, then spins. Only reproducing costs energy.
+fn replicator(partner: WordType, energy: u64) -> CPU {
+  let code = [
+    Instruction::LoadRelative(7),
+    Instruction::Move(2, 0),
+    Instruction::LoadRelative(6),
+    Instruction::Move(1, 0),
+    Instruction::BitXor(0, 0),
+    Instruction::Interrupt(INT_REPRODUCE),
+    Instruction::JumpRelative(-1, 0),
+  ];
+  let mut words: Vec<WordType> = code.iter().map(WordType::from).collect();
+  words.push(partner);
+  words.push(9);
+  let mut cpu = CPU::new(Memory::from(&words[..]), 0, 8);
+  cpu.meter(energy, Arc::new(CostTable::uniform(0)));
+  cpu
+}
+
+fn config(reproduction_cost: u64) -> PopulationConfig {
+  PopulationConfig {
+    reproduction_cost,
+    ..PopulationConfig::default()
+  }
+}
+
+#[test]
+fn reproduces() {
+  let mut subject = Subject::new(config(10), 1);
+  let parent = subject.add(replicator(NO_PARTNER, 25));
+
+  subject.run(7);
+  assert_eq!(2, subject.organisms().len());
+  let (parent, child) = (subject.get(parent).unwrap(), subject.get(1).unwrap());
+  assert_eq!(1, parent.cpu.register(0));
+  assert_eq!(0x0002, parent.cpu.register(13) & 0x0002);
+  assert_eq!(Some(15), parent.cpu.energy());
+  assert_eq!(Some(10), child.cpu.energy());
+  assert_eq!(parent.cpu.memory.raw(), child.cpu.memory.raw());
+  assert_eq!(0, child.cpu.register(14));
+
+  // Only the child is still at the start of its program.
+  subject.run(7);
+  assert_eq!(3, subject.organisms().len());
+  assert_eq!(Some(15), subject.get(0).unwrap().cpu.energy());
+  assert_eq!(Some(0), subject.get(1).unwrap().cpu.energy());
+  assert_eq!(2, subject.get(1).unwrap().cpu.register(0));
+}
+
+#[test]
+fn handles_are_unique_and_recycled() {
+  let mut subject = Subject::new(config(10), 1);
+  for handle in 0..NO_PARTNER {
+    subject.add(CPU::new(Memory::new(1), 0, 0));
+    assert_eq!(handle, subject.organisms().last().unwrap().handle);
+  }
+
+  // Every handle is taken, so newcomers can't be named and the reserved handle stays unused.
+  let unnamed = subject.add(CPU::new(Memory::new(1), 0, 0));
+  assert_eq!(NO_PARTNER, subject.get(unnamed).unwrap().handle);
+  assert!(subject.by_handle(NO_PARTNER).is_none());
+
+  assert_eq!(1, subject.retain(|organism| organism.handle != 5));
+  assert!(subject.by_handle(5).is_none());
+  let id = subject.add(CPU::new(Memory::new(1), 0, 0));
+  assert_eq!(0x10000, id);
+  assert_eq!(Some(id), subject.by_handle(5).map(|organism| organism.id));
+  assert_eq!(Some(0), subject.by_handle(0).map(|organism| organism.id));
+}
+
+#[test]
+fn runs_out_of_resources() {
+  let mut subject = Subject::new(config(10), 1);
+  subject.add(replicator(NO_PARTNER, 5));
+
+  subject.run(7);
+  let parent = subject.get(0).unwrap();
+  assert_eq!(1, subject.organisms().len());
+  assert_eq!(REPRODUCE_NO_RESOURCES, parent.cpu.register(0));
+  assert_eq!(0, parent.cpu.register(13) & 0x0002);
+}
+
+#[test]
+fn population_limit() {
+  let mut subject = Subject::new(PopulationConfig { max_population: 1, ..config(10) }, 1);
+  subject.add(replicator(NO_PARTNER, 100));
+
+  subject.run(7);
+  assert_eq!(1, subject.organisms().len());
+  assert_eq!(REPRODUCE_FULL, subject.get(0).unwrap().cpu.register(0));
+}
+
+#[test]
+fn bad_range() {
+  let mut cpu = replicator(NO_PARTNER, 100);
+  assert_eq!(Ok(()), cpu.borrow_mem().set(8, 200));
+  let mut subject = Subject::new(config(10), 1);
+  subject.add(cpu);
+
+  subject.run(7);
+  assert_eq!(REPRODUCE_BAD_RANGE, subject.get(0).unwrap().cpu.register(0));
+  assert_eq!(Some(100), subject.get(0).unwrap().cpu.energy());
+}
+
+#[test]
+fn with_partner() {
+  let mut subject = Subject::new(config(10), 1);
+  subject.add(replicator(1, 100));
+  subject.add(replicator(7, 100));
+
+  subject.run(7);
+  assert_eq!(3, subject.organisms().len());
+  assert_eq!(2, subject.get(0).unwrap().cpu.register(0));
+  assert_eq!(REPRODUCE_NO_PARTNER, subject.get(1).unwrap().cpu.register(0));
+  assert_eq!(9, subject.get(2).unwrap().cpu.memory.len());
+}
+
+#[test]
+fn faults_stop_organisms() {
+  let mut subject = Subject::new(config(10), 1);
+  subject.add(CPU::new(Memory::from(&[0, 0][..]), 0, 8));
+
+  subject.run(10);
+  let organism = subject.get(0).unwrap();
+  assert!(!organism.is_alive());
+  assert_eq!(Some(CPUErr::MemoryErr(MemoryErr::PointerOutOfRange(2, 2))), organism.fault);
+  assert_eq!(0, subject.living());
+}
+
+#[test]
+fn threaded_runs_the_same() {
+  let run = |threaded| {
+    let mut subject = Subject::new(PopulationConfig { mutation_rate: 0.5, threaded, ..config(10) }, 1);
+    subject.add(chooser());
+    subject.add(candidate());
+    subject.add(replicator(NO_PARTNER, 100));
+    subject.add(CPU::new(Memory::from(&[0, 0][..]), 0, 8));
+    for cycles in &[1, 7, 30, 30, 30] {
+      subject.run(*cycles);
+    }
+    subject
+  };
+
+  let (expected, subject) = (run(false), run(true));
+  assert_eq!(expected.organisms().len(), subject.organisms().len());
+  for (expected, organism) in expected.organisms().iter().zip(subject.organisms()) {
+    assert_eq!((expected.id, expected.age, &expected.fault), (organism.id, organism.age, &organism.fault));
+    assert_eq!(expected.cpu.registers, organism.cpu.registers);
+    assert_eq!(expected.cpu.memory.raw(), organism.cpu.memory.raw());
+  }
+  assert_eq!(expected.decisions(), subject.decisions());
+}
+
+#[test]
+fn unmetered_reproduction_is_free() {
+  let mut cpu = replicator(NO_PARTNER, 0);
+  let mut subject = Subject::new(config(10), 1);
+  cpu = CPU::new(Memory::from(cpu.memory.raw()), 0, 8);
+  subject.add(cpu);
+
+  subject.run(7);
+  assert_eq!(2, subject.organisms().len());
+  assert_eq!(None, subject.get(1).unwrap().cpu.energy());
+}
+
+#[test]
+fn transfer() {
+  let mut subject = Subject::new(config(10), 1);
+  subject.add(replicator(NO_PARTNER, 30));
+  subject.add(replicator(NO_PARTNER, 5));
+
+  assert_eq!(20, subject.transfer(0, 1, 20));
+  assert_eq!(10, subject.transfer(0, 1, 20));
+  assert_eq!(0, subject.transfer(0, 7, 20));
+  assert!(subject.grant(0, 3));
+  assert!(!subject.grant(7, 3));
+  assert_eq!(Some(3), subject.get(0).unwrap().cpu.energy());
+  assert_eq!(Some(35), subject.get(1).unwrap().cpu.energy());
+}
+
+#[test]
+fn records_lineage() {
+  let mut subject = Subject::new(config(10), 1);
+  subject.add(replicator(NO_PARTNER, 100));
+  subject.add(replicator(0, 100));
+
+  subject.run(7);
+  subject.run(7);
+  let lineage = subject.lineage();
+  assert_eq!(6, lineage.len());
+  assert_eq!(Vec::<u64>::new(), lineage.get(0).unwrap().parents);
+  assert_eq!(0, lineage.get(1).unwrap().generation);
+  assert_eq!(vec![0], lineage.get(2).unwrap().parents);
+  assert_eq!(vec![1, 0], lineage.get(3).unwrap().parents);
+  assert_eq!(1, lineage.get(3).unwrap().generation);
+  assert_eq!(vec![2], lineage.get(4).unwrap().parents);
+  assert_eq!(vec![3, 0], lineage.get(5).unwrap().parents);
+  assert_eq!(2, lineage.get(5).unwrap().generation);
+  assert!(lineage.records().iter().all(|record| record.mutations.is_empty()));
+}
+
+#[test]
+fn mutates_children() {
+  let mut subject = Subject::new(PopulationConfig { mutation_rate: 1.0, ..config(10) }, 1);
+  subject.add(replicator(NO_PARTNER, 100));
+
+  subject.run(7);
+  let (parent, child) = (subject.get(0).unwrap(), subject.get(1).unwrap());
+  let mutations = &subject.lineage().get(1).unwrap().mutations;
+  assert!(!mutations.is_empty());
+
+  // Replaying the log on the parent's genome gives the child's.
+  let mut genome = Memory::from(parent.cpu.memory.raw());
+  for mutation in mutations {
+    assert_eq!(Ok(()), mutation.apply(&mut genome));
+  }
+  assert_eq!(child.cpu.memory.raw(), genome.raw());
+}
+
+// Maps two words of organism 1 at r0, reads them into r4 and r3, then accepts it.
+fn chooser() -> CPU {
+  let code = [
+    Instruction::Interrupt(INT_INSPECT),
+    Instruction::Move(5, 0),
+    Instruction::Load(4, 5),
+    Instruction::Add(5, 6),
+    Instruction::Load(3, 5),
+    Instruction::Move(0, 7),
+    Instruction::Move(1, 6),
+    Instruction::Interrupt(INT_DECIDE),
+    Instruction::JumpRelative(-1, 0),
+  ];
+  let words: Vec<WordType> = code.iter().map(WordType::from).collect();
+  let mut cpu = CPU::new(Memory::from(&words[..]), 0, 8);
+  for (reg, value) in &[(0, 1), (1, 0), (2, 2), (6, 1), (7, 1)] {
+    cpu.set_register(*reg, *value);
+  }
+  cpu
+}
+
+fn candidate() -> CPU {
+  CPU::new(Memory::from(&[WordType::from(Instruction::JumpRelative(-1, 0)), 0x1234][..]), 0, 8)
+}
+
+#[test]
+fn inspects_and_decides() {
+  let mut subject = Subject::new(config(10), 1);
+  subject.add(chooser());
+  subject.add(candidate());
+
+  subject.run(1);
+  assert_eq!(9, subject.get(0).unwrap().cpu.register(0));
+  assert_eq!(Some(&[WordType::from(Instruction::JumpRelative(-1, 0)), 0x1234][..]), subject.get(0).unwrap().cpu.window());
+
+  subject.run(8);
+  let chooser = &subject.get(0).unwrap().cpu;
+  assert_eq!(WordType::from(Instruction::JumpRelative(-1, 0)), chooser.register(4));
+  assert_eq!(0x1234, chooser.register(3));
+  assert_eq!(None, chooser.window());
+  assert_eq!(&[Decision { chooser: 0, candidate: 1, accepted: true }], subject.decisions());
+}
+
+#[test]
+fn decisions_record_ids() {
+  let mut subject = Subject::new(config(10), 1);
+  subject.add(chooser());
+  for _ in 1..NO_PARTNER {
+    subject.add(CPU::new(Memory::new(1), 0, 0));
+  }
+  subject.retain(|organism| organism.id == 0);
+
+  // The candidate gets the freed handle 1 long after id 1 was given out.
+  let candidate = subject.add(candidate());
+  assert_eq!(Some(candidate), subject.by_handle(1).map(|organism| organism.id));
+  assert_ne!(1, candidate);
+
+  subject.run(9);
+  assert_eq!(1, subject.get(0).unwrap().cpu.register(0));
+  assert_eq!(&[Decision { chooser: 0, candidate, accepted: true }], subject.decisions());
+}
+
+#[test]
+fn inspect_failures() {
+  let mut subject = Subject::new(config(10), 1);
+  let mut cpu = chooser();
+  cpu.set_register(0, 0);
+  subject.add(cpu);
+  let mut cpu = chooser();
+  cpu.set_register(0, 0);
+  cpu.set_register(2, 20);
+  subject.add(cpu);
+
+  subject.run(1);
+  assert_eq!(INSPECT_NO_CANDIDATE, subject.get(0).unwrap().cpu.register(0));
+  assert_eq!(0, subject.get(0).unwrap().cpu.register(13) & 0x0002);
+  assert_eq!(INSPECT_BAD_RANGE, subject.get(1).unwrap().cpu.register(0));
+  assert_eq!(None, subject.get(1).unwrap().cpu.window());
+}
+
+#[test]
+fn window_is_read_only_and_expires() {
+  let code = [
+    Instruction::Load(1, 0),
+    Instruction::Save(0, 1),
+  ];
+  let words: Vec<WordType> = code.iter().map(WordType::from).collect();
+  let mut cpu = CPU::new(Memory::from(&words[..]), 0, 8);
+  assert_eq!(2, cpu.map_window(vec![7, 8], 1));
+  cpu.set_register(0, 3);
+
+  assert_eq!(Ok(()), cpu.step());
+  assert_eq!(8, cpu.register(1));
+  assert_eq!(Err(CPUErr::MemoryErr(MemoryErr::PointerOutOfRange(2, 3))), cpu.step());
+
+  // One instruction has run since mapping, the next one finds the window gone.
+  cpu.set_register(14, 0);
+  assert_eq!(Err(CPUErr::MemoryErr(MemoryErr::PointerOutOfRange(2, 3))), cpu.step());
+  assert_eq!(None, cpu.window());
+}
+
+fn pair(provider: CPU, selector: CPU) -> PairMachine {
+  PairMachine::new(provider, selector, 4)
+}
+
+#[test]
+fn pairs_split_roles() {
+  let provider = CPU::new(Memory::from(&[WordType::from(Instruction::Interrupt(INT_INSPECT)), 0][..]), 0, 8);
+  let selector = CPU::new(Memory::from(&[WordType::from(Instruction::Interrupt(INT_REPRODUCE)), 0][..]), 0, 8);
+  let mut subject = Subject::new(config(10), 1);
+  subject.add_pair(pair(provider, selector));
+
+  subject.run(1);
+  let organism = subject.get(0).unwrap();
+  for role in &[Role::Provider, Role::Selector] {
+    let cpu = organism.half(*role).unwrap();
+    assert_eq!((WRONG_ROLE, 0), (cpu.register(0), cpu.register(13) & 0x0002));
+  }
+  assert_eq!(1, subject.organisms().len());
+}
+
+#[test]
+fn pairs_mate_with_accepted_partners() {
+  // Retries INT_REPRODUCE with partner 1 forever.
+  let mut provider = replicator(1, 100);
+  assert_eq!(Ok(()), provider.borrow_mem().set(6, WordType::from(Instruction::JumpRelative(-3, 0))));
+  let mut subject = Subject::new(config(10), 1);
+  subject.add_pair(pair(provider, chooser()));
+  subject.add(candidate());
+
+  // The selector only accepts the candidate on the 8th cycle.
+  subject.run(6);
+  assert_eq!(REPRODUCE_NOT_ACCEPTED, subject.get(0).unwrap().cpu.register(0));
+  subject.run(3);
+  assert_eq!(&[Decision { chooser: 0, candidate: 1, accepted: true }], subject.decisions());
+  let parent = subject.get(0).unwrap();
+  assert_eq!(2, parent.cpu.register(0));
+
+  let child = subject.get(2).unwrap();
+  assert_eq!(vec![0, 1], subject.lineage().get(2).unwrap().parents);
+  assert_eq!(parent.selector.as_ref().unwrap().memory.raw(), child.selector.as_ref().unwrap().memory.raw());
+}
+
+#[test]
+fn pairs_die_together() {
+  let selector = CPU::new(Memory::from(&[WordType::from(Instruction::Nop), WordType::from(Instruction::PopRegister(0))][..]), 0, 8);
+  let mut subject = Subject::new(config(10), 1);
+  subject.add_pair(pair(replicator(NO_PARTNER, 100), selector));
+
+  subject.run(5);
+  let organism = subject.get(0).unwrap();
+  assert_eq!(Some(CPUErr::StackUnderflow), organism.fault);
+  assert_eq!(2, organism.cpu.register(14));
+  assert_eq!(1, subject.organisms().len());
+}
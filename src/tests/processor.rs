@@ -0,0 +1,171 @@
+use std::thread;
+
+use crate::cpu::{CPU, WordType};
+use crate::instruction::Instruction;
+use crate::instruction::codes::COND_NOT_COMPARISON;
+use crate::machine::atomic_memory::AtomicMemory;
+use crate::machine::memory::{Backend, Memory, MemoryErr, SharedMemory};
+use crate::memory::Memory as CPUMemory;
+use crate::machine::processor::{
+  Processor as Subject,
+  ProcessorError,
+};
+
+const PC: usize = 14;
+const COMPARISON: WordType = 0x0002;
+
+fn program(code: &[Instruction]) -> SharedMemory {
+  let memory = Memory::new(code.len() as WordType + 4);
+  for (pos, instruction) in code.iter().enumerate() {
+    assert_eq!(Ok(()), memory.write().unwrap().set(pos as WordType, WordType::from(instruction)));
+  }
+  memory
+}
+
+const FETCH_ADD: [Instruction; 5] = [
+  Instruction::Move(2, 3),
+  Instruction::FetchAdd(1, 2),
+  Instruction::Subtract(4, 3),
+  Instruction::Not(4),
+  Instruction::JumpRelative(-5, COND_NOT_COMPARISON),
+];
+
+// Reads the counter and retries the increment until nobody else got there first.
+const COMPARE_AND_SWAP: [Instruction; 8] = [
+  Instruction::Load(0, 1),
+  Instruction::Move(2, 0),
+  Instruction::Add(2, 3),
+  Instruction::CompareAndSwap(1, 2),
+  Instruction::JumpRelative(-5, COND_NOT_COMPARISON),
+  Instruction::Subtract(4, 3),
+  Instruction::Not(4),
+  Instruction::JumpRelative(-8, COND_NOT_COMPARISON),
+];
+
+// Loads r3 = 1, r1 = the counter address and r4 = `iterations`, then runs `body` from 6 until r4
+// reaches zero and parks on a jump to itself. The counter is the last word of memory, which needs
+// room for the code plus four words.
+fn counting(memory: &impl Backend, body: &[Instruction], iterations: WordType) -> WordType {
+  let data = 6 + body.len() as i8 + 1;
+  let mut code = vec![
+    Instruction::LoadRelative(data),
+    Instruction::Move(3, 0),
+    Instruction::LoadRelative(data - 1),
+    Instruction::Move(1, 0),
+    Instruction::LoadRelative(data - 2),
+    Instruction::Move(4, 0),
+  ];
+  code.extend_from_slice(body);
+  code.push(Instruction::JumpRelative(-1, 0));
+  let halt = code.len() as WordType - 1;
+
+  for (pos, instruction) in code.iter().enumerate() {
+    memory.set(pos as WordType, WordType::from(instruction)).unwrap();
+  }
+  for (pos, value) in [1, memory.len() - 1, iterations].iter().enumerate() {
+    memory.set(halt + 1 + pos as WordType, *value).unwrap();
+  }
+  halt
+}
+
+// Runs a processor per thread over the same memory until every one of them has halted and returns
+// the final counter.
+fn contend<M: Backend + Clone + Send>(memory: M, body: &[Instruction], processors: usize) -> WordType {
+  let halt = counting(&memory, body, 2000);
+  thread::scope(|scope| {
+    for _ in 0..processors {
+      let memory = memory.clone();
+      scope.spawn(move || {
+        let mut subject = Subject::new(memory, 8, 0);
+        while subject.registers()[PC] != halt {
+          assert_eq!(Ok(()), subject.step());
+        }
+      });
+    }
+  });
+  memory.get(memory.len() - 1).unwrap()
+}
+
+#[test]
+fn atomic_operations() {
+  let memory = program(&[
+    Instruction::LoadRelative(12),
+    Instruction::Move(1, 0),
+    Instruction::LoadRelative(11),
+    Instruction::Move(2, 0),
+    Instruction::LoadRelative(10),
+    Instruction::Move(4, 0),
+    Instruction::BitXor(0, 0),
+    Instruction::CompareAndSwap(1, 2),
+    Instruction::Move(3, 13),
+    Instruction::CompareAndSwap(1, 2),
+    Instruction::FetchAdd(1, 2),
+    Instruction::Swap(1, 4),
+  ]);
+  memory.write().unwrap().set_range(12, &[15, 5, 7]).unwrap();
+  let mut subject = Subject::new(memory.clone(), 8, 0);
+  for _ in 0..12 {
+    assert_eq!(Ok(()), subject.step());
+  }
+
+  let registers = subject.registers();
+  assert_eq!(COMPARISON, registers[3] & COMPARISON);
+  assert_eq!((5, 0), (registers[0], registers[13] & COMPARISON));
+  assert_eq!((5, 10), (registers[2], registers[4]));
+  assert_eq!(Ok(7), memory.read().unwrap().get(15));
+}
+
+#[test]
+fn fetch_add_loses_no_updates() {
+  assert_eq!(16000, contend(Memory::new(16), &FETCH_ADD, 8));
+  assert_eq!(16000, contend(AtomicMemory::new(16), &FETCH_ADD, 8));
+}
+
+#[test]
+fn compare_and_swap_loses_no_updates() {
+  assert_eq!(16000, contend(Memory::new(19), &COMPARE_AND_SWAP, 8));
+  assert_eq!(16000, contend(AtomicMemory::new(19), &COMPARE_AND_SWAP, 8));
+}
+
+#[test]
+fn atomic_faults() {
+  let mut subject = Subject::new(program(&[Instruction::BitNot(1), Instruction::FetchAdd(1, 2)]), 8, 0);
+  assert_eq!(Ok(()), subject.step());
+  assert_eq!(Err(ProcessorError::MemoryErr(MemoryErr::PointerOutOfRange(6, 0xFFFF))), subject.step());
+}
+
+#[test]
+fn empty_memory_faults() {
+  let mut subject = Subject::new(Memory::new(0), 8, 0);
+  assert_eq!(Err(ProcessorError::MemoryErr(MemoryErr::PointerOutOfRange(0, 0))), subject.step());
+  assert_eq!(0, subject.registers()[PC]);
+}
+
+#[test]
+fn runs_the_cpu_instruction_set() {
+  let code = [
+    Instruction::LoadRelative(8),
+    Instruction::Move(4, 0),
+    Instruction::LoadRelative(7),
+    Instruction::BitNot(1),
+    Instruction::SignedLessThan(1, 2),
+    Instruction::PushRegister(1),
+    Instruction::Peek(3, 0),
+    Instruction::FillBlock(4, 1),
+  ];
+  let mut words: Vec<WordType> = code.iter().map(WordType::from).collect();
+  words.extend_from_slice(&[12, 2]);
+  words.resize(16, 0);
+
+  let memory = Memory::new(16);
+  memory.write().unwrap().set_range(0, &words).unwrap();
+  let mut subject = Subject::new(memory.clone(), 8, 0);
+  let mut cpu = CPU::new(CPUMemory::from(&words[..]), 0, 8);
+  for _ in 0..code.len() {
+    assert_eq!(Ok(()), subject.step());
+    assert_eq!(Ok(()), cpu.step());
+  }
+  assert_eq!(&cpu.registers, subject.registers());
+  assert_eq!((COMPARISON, 0xFFFF), (subject.registers()[13] & COMPARISON, subject.registers()[3]));
+  assert_eq!(Ok(&[0xFFFF, 0xFFFF][..]), memory.read().unwrap().get_range(12, 2));
+}
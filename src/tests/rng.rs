@@ -0,0 +1,108 @@
+use crate::cpu::{CPU, WordType, INT_RANDOM};
+use crate::instruction::Instruction;
+use crate::memory::Memory;
+use crate::population::{Population, PopulationConfig};
+use crate::rng::Rng as Subject;
+use crate::threaded::ThreadedCPU;
+
+// Draws a random word into r5, then two below 10 into r2 and r3.
+fn dice() -> CPU {
+  let code = [
+    Instruction::Interrupt(INT_RANDOM),
+    Instruction::Move(5, 0),
+    Instruction::LoadRelative(6),
+    Instruction::Move(1, 0),
+    Instruction::Interrupt(INT_RANDOM),
+    Instruction::Move(2, 0),
+    Instruction::Interrupt(INT_RANDOM),
+    Instruction::Move(3, 0),
+  ];
+  let mut words: Vec<WordType> = code.iter().map(WordType::from).collect();
+  words.push(10);
+  CPU::new(Memory::from(&words[..]), 0, 8)
+}
+
+fn draws(cpu: &CPU) -> [WordType; 3] {
+  [cpu.register(5), cpu.register(2), cpu.register(3)]
+}
+
+#[test]
+fn state_round_trip() {
+  let mut subject = Subject::new(42);
+  subject.next_u64();
+  let mut restored = Subject::from_state(subject.state());
+
+  assert_eq!(subject, restored);
+  assert_eq!(subject.next_u64(), restored.next_u64());
+  assert_ne!(0, Subject::from_state(0).state());
+}
+
+#[test]
+fn machine_streams() {
+  assert_eq!(Subject::for_machine(1, 7), Subject::for_machine(1, 7));
+  assert_ne!(Subject::for_machine(1, 7), Subject::for_machine(1, 8));
+  assert_ne!(Subject::for_machine(1, 7), Subject::for_machine(2, 7));
+}
+
+#[test]
+fn interrupt() {
+  let mut cpu = dice();
+  cpu.set_rng(Subject::new(3));
+  for _ in 0..8 {
+    assert_eq!(Ok(()), cpu.step());
+  }
+
+  let mut expected = Subject::new(3);
+  assert_eq!(expected.word(), cpu.register(5));
+  assert_eq!(expected.below(10) as WordType, cpu.register(2));
+  assert_eq!(expected.below(10) as WordType, cpu.register(3));
+  assert!(draws(&cpu)[1..].iter().all(|draw| *draw < 10));
+  assert_eq!(None, cpu.take_interrupt());
+  assert_eq!(&expected, cpu.rng());
+}
+
+#[test]
+fn restores_mid_run() {
+  let mut cpu = dice();
+  cpu.step().unwrap();
+  let state = cpu.rng().state();
+  for _ in 1..8 {
+    cpu.step().unwrap();
+  }
+
+  // A fresh machine picking up the saved state at the same point draws the same numbers.
+  let mut subject = dice();
+  subject.set_rng(Subject::from_state(state));
+  subject.set_register(14, 1);
+  for _ in 1..8 {
+    subject.step().unwrap();
+  }
+  assert_eq!(draws(&cpu)[1..], draws(&subject)[1..]);
+}
+
+#[test]
+fn seeded_per_organism() {
+  let run = |seed| {
+    let mut population = Population::new(PopulationConfig::default(), seed);
+    population.add(dice());
+    population.add(dice());
+    population.run(8);
+    population.organisms().iter().map(|organism| draws(&organism.cpu)).collect::<Vec<_>>()
+  };
+
+  assert_eq!(run(1), run(1));
+  assert_ne!(run(1), run(2));
+  assert_ne!(run(1)[0], run(1)[1]);
+}
+
+#[test]
+fn threaded_draws_the_same() {
+  let mut expected = dice();
+  for _ in 0..8 {
+    expected.step().unwrap();
+  }
+  let mut subject = ThreadedCPU::new(dice());
+
+  assert_eq!((8, Ok(())), subject.run(8));
+  assert_eq!(draws(&expected), draws(subject.cpu()));
+}
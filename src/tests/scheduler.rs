@@ -0,0 +1,157 @@
+use crate::cpu::WordType;
+use crate::instruction::Instruction;
+use crate::machine::mailbox::Message;
+use crate::machine::atomic_memory::AtomicMemory;
+use crate::machine::memory::{Backend, Memory, SharedMemory};
+use crate::machine::processor::{
+  Processor,
+  ProcessorError,
+  INT_SEND,
+  INT_RECEIVE,
+  INT_POLL,
+};
+use crate::machine::scheduler::{
+  Scheduler as Subject,
+  ExitReason,
+};
+
+fn program(code: &[Instruction]) -> SharedMemory {
+  let memory = Memory::new(code.len() as WordType);
+  for (pos, instruction) in code.iter().enumerate() {
+    assert_eq!(Ok(()), memory.write().unwrap().set(pos as WordType, WordType::from(instruction)));
+  }
+  memory
+}
+
+#[test]
+fn runs_until_cycle_limit() {
+  let memory = program(&[Instruction::Nop, Instruction::JumpRelative(-2, 0)]);
+  let mut subject = Subject::new(4, 7);
+  for _ in 0..16 {
+    subject.add(Processor::new(memory.clone(), 8, 0));
+  }
+
+  let reports = subject.run(1000);
+  assert_eq!(16, reports.len());
+  for (id, report) in reports.iter().enumerate() {
+    assert_eq!(id, report.id);
+    assert_eq!(1000, report.cycles);
+    assert_eq!(ExitReason::CycleLimit, report.exit);
+  }
+}
+
+#[test]
+fn reports_faults() {
+  let looping = program(&[Instruction::Nop, Instruction::JumpRelative(-2, 0)]);
+  let faulting = program(&[Instruction::Nop, Instruction::Nop, Instruction::Divide(1, 2)]);
+  let mut subject = Subject::new(3, 5);
+  subject.add(Processor::new(looping.clone(), 8, 0));
+  subject.add(Processor::new(faulting, 8, 0));
+  subject.add(Processor::new(looping, 8, 0));
+
+  let reports = subject.run(100);
+  assert_eq!(100, reports[0].cycles);
+  assert_eq!(ExitReason::CycleLimit, reports[0].exit);
+  assert_eq!(3, reports[1].cycles);
+  assert_eq!(ExitReason::Fault(ProcessorError::DivideByZero), reports[1].exit);
+  assert!(!subject.actor(1).unwrap().processor().read().unwrap().is_running());
+  assert_eq!(100, reports[2].cycles);
+
+  let reports = subject.run(250);
+  assert_eq!(250, reports[0].cycles);
+  assert_eq!(3, reports[1].cycles);
+  assert_eq!(ExitReason::Fault(ProcessorError::DivideByZero), reports[1].exit);
+  assert_eq!(250, reports[2].cycles);
+
+  // An empty memory is just another fault.
+  subject.add(Processor::new(Memory::new(0), 8, 0));
+  let reports = subject.run(300);
+  assert_eq!(300, reports[0].cycles);
+  assert!(matches!(reports[3].exit, ExitReason::Fault(ProcessorError::MemoryErr(_))));
+}
+
+#[test]
+fn exchanges_messages() {
+  let ping = program(&[
+    Instruction::LoadRelative(8),
+    Instruction::Move(1, 0),
+    Instruction::LoadRelative(7),
+    Instruction::Interrupt(INT_SEND),
+    Instruction::Interrupt(INT_RECEIVE),
+    Instruction::Move(3, 0),
+    Instruction::Interrupt(INT_RECEIVE),
+    Instruction::Nop,
+    Instruction::Nop,
+    Instruction::Nop,
+  ]);
+  ping.write().unwrap().set(8, 42).unwrap();
+  ping.write().unwrap().set(9, 1).unwrap();
+  let pong = program(&[
+    Instruction::Interrupt(INT_RECEIVE),
+    Instruction::Move(2, 0),
+    Instruction::Move(0, 1),
+    Instruction::Move(1, 2),
+    Instruction::Add(1, 1),
+    Instruction::Interrupt(INT_SEND),
+    Instruction::Interrupt(INT_RECEIVE),
+  ]);
+
+  let mut subject = Subject::new(2, 3);
+  subject.add(Processor::new(ping, 8, 0));
+  subject.add(Processor::new(pong, 8, 0));
+
+  let reports = subject.run(1000);
+  assert_eq!(ExitReason::Waiting, reports[0].exit);
+  assert_eq!(7, reports[0].cycles);
+  assert_eq!(ExitReason::Waiting, reports[1].exit);
+  assert_eq!(7, reports[1].cycles);
+  assert_eq!(84, subject.actor(0).unwrap().processor().read().unwrap().registers()[3]);
+  assert_eq!(42, subject.actor(1).unwrap().processor().read().unwrap().registers()[2]);
+}
+
+#[test]
+fn polls_for_messages() {
+  let memory = program(&[
+    Instruction::Interrupt(INT_POLL),
+    Instruction::Move(2, 13),
+    Instruction::Interrupt(INT_POLL),
+    Instruction::Interrupt(INT_RECEIVE),
+  ]);
+  let mut subject = Subject::new(1, 10);
+  subject.add(Processor::new(memory, 8, 0));
+  assert!(subject.postbox().send(0, Message { from: 99, value: 7 }));
+
+  let reports = subject.run(1000);
+  assert_eq!(ExitReason::Waiting, reports[0].exit);
+  assert_eq!(4, reports[0].cycles);
+  let processor = subject.actor(0).unwrap().processor().read().unwrap();
+  assert_eq!(7, processor.registers()[0]);
+  assert_eq!(99, processor.registers()[1]);
+  assert_eq!(0x0002, processor.registers()[2]);
+  assert_eq!(0, processor.registers()[13]);
+  assert!(processor.is_waiting());
+}
+
+#[test]
+fn runs_any_backend() {
+  // Every processor adds 1 to the word at 7 a hundred times.
+  let code = [
+    Instruction::LoadRelative(5),
+    Instruction::Move(1, 0),
+    Instruction::LoadRelative(4),
+    Instruction::FetchAdd(1, 0),
+    Instruction::JumpRelative(-3, 0),
+  ];
+  let memory = AtomicMemory::new(8);
+  for (pos, word) in code.iter().map(WordType::from).chain(vec![7, 1]).enumerate() {
+    memory.set(pos as WordType, word).unwrap();
+  }
+  let mut subject = Subject::new(4, 7);
+  for _ in 0..4 {
+    subject.add(Processor::new(memory.clone(), 8, 0));
+  }
+
+  let reports = subject.run(302);
+  assert!(reports.iter().all(|report| report.exit == ExitReason::CycleLimit));
+  assert_eq!(Ok(400), memory.get(7));
+}
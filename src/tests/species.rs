@@ -0,0 +1,64 @@
+use crate::cpu::{CPU, WordType};
+use crate::instruction::Instruction;
+use crate::memory::Memory;
+use crate::population::{Population, PopulationConfig};
+use crate::species::*;
+use crate::species::Metric as Subject;
+
+fn image(code: &[Instruction]) -> Memory {
+  let words: Vec<WordType> = code.iter().map(WordType::from).collect();
+  Memory::from(&words[..])
+}
+
+#[test]
+fn hash_is_stable() {
+  let memory = Memory::from(&[0x0102, 0x0304][..]);
+
+  assert_eq!(genome_hash(&memory), genome_hash(&Memory::from(&[0x0102, 0x0304][..])));
+  assert_ne!(genome_hash(&memory), genome_hash(&Memory::from(&[0x0304, 0x0102][..])));
+  assert_eq!(0xCBF2_9CE4_8422_2325, genome_hash(&Memory::new(0)));
+  // FNV-1a of the bytes 02 01 04 03.
+  assert_eq!(0x8463_BE90_4546_06E1, genome_hash(&memory));
+}
+
+#[test]
+fn hamming() {
+  let first = Memory::from(&[1, 2, 3, 4][..]);
+
+  assert_eq!(0, Subject::Hamming.distance(&first, &first));
+  assert_eq!(2, Subject::Hamming.distance(&first, &Memory::from(&[1, 5, 3, 6][..])));
+  assert_eq!(2, Subject::Hamming.distance(&first, &Memory::from(&[1, 2][..])));
+  assert_eq!(4, Subject::Hamming.distance(&first, &Memory::from(&[2, 3, 4, 5][..])));
+}
+
+#[test]
+fn edit() {
+  let first = image(&[Instruction::Add(1, 2), Instruction::Nop, Instruction::Move(0, 1)]);
+  let shifted = image(&[Instruction::Nop, Instruction::Add(1, 2), Instruction::Nop, Instruction::Move(0, 1)]);
+  let changed = image(&[Instruction::Add(1, 3), Instruction::Nop, Instruction::Move(0, 1)]);
+
+  assert_eq!(0, Subject::Edit.distance(&first, &first));
+  assert_eq!(1, Subject::Edit.distance(&first, &shifted));
+  assert_eq!(1, Subject::Edit.distance(&shifted, &first));
+  assert_eq!(1, Subject::Edit.distance(&first, &changed));
+  assert_eq!(3, Subject::Edit.distance(&first, &Memory::new(0)));
+  assert_eq!(4, Subject::Hamming.distance(&first, &shifted));
+}
+
+#[test]
+fn clusters() {
+  let mut population = Population::new(PopulationConfig::default(), 1);
+  for words in &[[1, 2, 3, 4], [9, 9, 9, 9], [1, 2, 3, 5], [1, 2, 3, 4], [9, 9, 9, 8], [1, 7, 7, 4]] {
+    population.add(CPU::new(Memory::from(&words[..]), 0, 8));
+  }
+
+  let species = cluster(population.organisms(), Subject::Hamming, 1);
+  assert_eq!(3, species.len());
+  assert_eq!(Species { representative: 0, members: vec![0, 2, 3] }, species[0]);
+  assert_eq!(Species { representative: 1, members: vec![1, 4] }, species[1]);
+  assert_eq!(Species { representative: 5, members: vec![5] }, species[2]);
+
+  assert_eq!(5, cluster(population.organisms(), Subject::Hamming, 0).len());
+  assert_eq!(1, cluster(population.organisms(), Subject::Hamming, 4).len());
+  assert!(cluster(&[], Subject::Edit, 4).is_empty());
+}
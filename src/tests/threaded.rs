@@ -0,0 +1,152 @@
+use crate::cpu::{CPU, CPUErr, Growth, StackMode, WordType, FLAG_INTERRUPTS, FLAG_TIMER, VEC_TIMER};
+use crate::instruction::Instruction;
+use crate::memory::Memory;
+use crate::threaded::ThreadedCPU as Subject;
+
+fn xorshift(state: &mut u32) -> u32 {
+  *state ^= *state << 13;
+  *state ^= *state >> 17;
+  *state ^= *state << 5;
+  *state
+}
+
+fn random_program(seed: u32, size: usize) -> Vec<WordType> {
+  let mut state = seed;
+  (0..size).map(|_| xorshift(&mut state) as WordType).collect()
+}
+
+fn cpu(program: &[WordType]) -> CPU {
+  let mut memory = Memory::new(program.len() as WordType);
+  assert_eq!(Ok(()), memory.set_range(0, program));
+  CPU::new(memory, 0, 32)
+}
+
+// `program` with its stack over its own code, a timer of `period` and a vector table at 48 whose
+// handlers all lie inside the program.
+fn trapping_cpu(program: &[WordType], period: WordType) -> CPU {
+  let mut memory = Memory::new(program.len() as WordType);
+  assert_eq!(Ok(()), memory.set_range(0, program));
+  for vector in 48..(program.len() as WordType) {
+    assert_eq!(Ok(()), memory.set(vector, program[vector as usize] % 48));
+  }
+  let mut cpu = CPU::with_stack(memory, 0, StackMode::InMemory { base: 8, size: 32, growth: Growth::Up });
+  cpu.set_vectors(48);
+  cpu.set_timer(period);
+  cpu.registers[13] = FLAG_INTERRUPTS | FLAG_TIMER;
+  cpu
+}
+
+fn assert_same(expected: &CPU, actual: &CPU) {
+  assert_eq!(expected.registers, actual.registers);
+  assert_eq!(expected.memory.raw(), actual.memory.raw());
+  assert_eq!(expected.stack.raw(), actual.stack.raw());
+}
+
+#[test]
+fn matches_interpreter_per_instruction() {
+  for seed in 1..200 {
+    let program = random_program(seed, 64);
+    let mut expected = cpu(&program);
+    let mut subject = Subject::new(cpu(&program));
+
+    for _ in 0..500 {
+      let res = expected.step();
+      assert_eq!((1, res), subject.run(1));
+      assert_eq!(expected.take_interrupt(), subject.take_interrupt());
+      assert_same(&expected, subject.cpu());
+    }
+  }
+}
+
+#[test]
+fn matches_interpreter_in_bulk() {
+  for seed in 1..200 {
+    let program = random_program(seed, 64);
+    let mut trace = cpu(&program);
+    let results: Vec<Result<(), CPUErr>> = (0..2000).map(|_| trace.step()).collect();
+
+    let mut expected = cpu(&program);
+    let mut subject = Subject::new(cpu(&program));
+    let mut executed = 0;
+    while executed < results.len() {
+      let (count, res) = subject.run(results.len() - executed);
+      (0..count).for_each(|_| { let _ = expected.step(); });
+
+      // The engine only stops early on the first error.
+      let taken = &results[executed..(executed + count)];
+      assert!(taken[..(count - 1)].iter().all(|res| res.is_ok()));
+      assert_eq!(taken[count - 1], res);
+      assert_eq!(expected.take_interrupt(), subject.take_interrupt());
+      assert_same(&expected, subject.cpu());
+      executed += count;
+    }
+  }
+}
+
+#[test]
+fn matches_interpreter_with_interrupts() {
+  for seed in 1..200 {
+    let program = random_program(seed, 64);
+    let mut expected = trapping_cpu(&program, 3 + (seed % 5) as WordType);
+    let mut subject = Subject::new(trapping_cpu(&program, 3 + (seed % 5) as WordType));
+
+    for _ in 0..500 {
+      let res = expected.step();
+      assert_eq!((1, res), subject.run(1));
+      assert_eq!(expected.take_interrupt(), subject.take_interrupt());
+      assert_same(&expected, subject.cpu());
+    }
+  }
+}
+
+#[test]
+fn interrupts_pushed_over_code() {
+  // The timer handler returns straight away, but delivering it pushes over the loop's Nops.
+  let code = [Instruction::Nop, Instruction::Nop, Instruction::Add(6, 7), Instruction::JumpRelative(-4, 0)];
+  let make = || {
+    let mut memory = Memory::new(32);
+    for (pos, instruction) in code.iter().enumerate() {
+      assert_eq!(Ok(()), memory.set(pos as WordType, WordType::from(instruction)));
+    }
+    assert_eq!(Ok(()), memory.set(10, WordType::from(Instruction::ReturnFromInterrupt)));
+    assert_eq!(Ok(()), memory.set(20 + VEC_TIMER, 10));
+    let mut cpu = CPU::with_stack(memory, 0, StackMode::InMemory { base: 1, size: 4, growth: Growth::Up });
+    cpu.set_vectors(20);
+    cpu.set_timer(6);
+    cpu.registers[7] = 1;
+    cpu.registers[13] = FLAG_INTERRUPTS | FLAG_TIMER;
+    cpu
+  };
+
+  // RETI pops the overwritten words and underflows on the second time round.
+  let mut expected = make();
+  let mut executed = 0;
+  let mut res = Ok(());
+  while executed < 12 && res.is_ok() {
+    res = expected.step();
+    executed += 1;
+  }
+  assert_eq!(Err(CPUErr::StackUnderflow), res);
+
+  let mut subject = Subject::new(make());
+  assert_eq!((executed, res), subject.run(12));
+  assert_same(&expected, subject.cpu());
+}
+
+#[test]
+fn self_modifying_code() {
+  let program = [
+    WordType::from(Instruction::LoadRelative(5)),
+    WordType::from(Instruction::SaveRelative(2)),
+    WordType::from(Instruction::Nop),
+    WordType::from(Instruction::Nop),
+    WordType::from(Instruction::JumpRelative(-1, 0)),
+    WordType::from(Instruction::BitNot(1)),
+  ];
+  let mut subject = Subject::new(cpu(&program));
+
+  assert_eq!((10, Ok(())), subject.run(10));
+  assert_eq!(WordType::from(Instruction::BitNot(1)), subject.cpu().memory.raw()[3]);
+  assert_eq!(0xFFFF, subject.cpu().registers[1]);
+}
+
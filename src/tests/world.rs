@@ -0,0 +1,181 @@
+use crate::cpu::{CPU, CPUErr, WordType};
+use crate::instruction::Instruction;
+use crate::instruction::codes::*;
+use crate::memory::Memory;
+use crate::population::PopulationConfig;
+use crate::species::Metric;
+use crate::world::*;
+use crate::world::World as Subject;
+
+const REPLICATOR: &str = "
+  # Copies itself into a child over and over.
+  0x071c 0x0045 0x061c 0x0025 0x0018 0x0413 0xf91d
+  0xffff 9
+";
+
+#[test]
+fn parses_toml() {
+  let config = WorldConfig::parse("
+    seed = 7
+    cycles = 50 # per generation
+    stats = \"out.csv\"
+
+    [population]
+    max = 20
+    max_age = 1_000
+    max_threads = 4
+    threaded = true
+
+    [energy]
+    per_word = 2
+
+    [genomes]
+    programs = [\"a.prog\", \"b.prog\"]
+
+    [mutation]
+    rate = 0.5
+
+    [species]
+    metric = \"edit\"
+    threshold = 3
+  ").unwrap();
+
+  assert_eq!(7, config.seed);
+  assert_eq!(50, config.cycles);
+  assert_eq!(Some(String::from("out.csv")), config.stats);
+  assert_eq!(20, config.population.max_population);
+  assert_eq!(Some(1000), config.max_age);
+  assert_eq!(4, config.population.max_threads);
+  assert!(config.population.threaded);
+  assert_eq!(2, config.energy_per_word);
+  assert_eq!(vec![String::from("a.prog"), String::from("b.prog")], config.programs);
+  assert_eq!(0.5, config.population.mutation_rate);
+  assert_eq!(Metric::Edit, config.species_metric);
+  assert_eq!(3, config.species_threshold);
+  assert_eq!(WorldConfig::default().generations, config.generations);
+}
+
+#[test]
+fn parses_ini() {
+  let config = WorldConfig::parse("
+    ; comment
+    [genomes]
+    programs = a.prog, b.prog
+    random = 3
+  ").unwrap();
+
+  assert_eq!(vec![String::from("a.prog"), String::from("b.prog")], config.programs);
+  assert_eq!(3, config.random_genomes);
+}
+
+#[test]
+fn config_errors() {
+  assert_eq!(Err(ConfigErr::Syntax(2)), WorldConfig::parse("seed = 1\nseed\n"));
+  assert_eq!(Err(ConfigErr::UnknownKey(String::from("energy.seed"))), WorldConfig::parse("[energy]\nseed = 1"));
+  assert_eq!(Err(ConfigErr::BadValue(String::from("cycles"))), WorldConfig::parse("cycles = many"));
+}
+
+#[test]
+fn parses_programs() {
+  let memory = parse_program(REPLICATOR).unwrap();
+
+  assert_eq!(9, memory.len());
+  assert_eq!(Ok(WordType::from(Instruction::Interrupt(4))), memory.get(5));
+  assert_eq!(Ok(9), memory.get(8));
+  assert_eq!(Some(ConfigErr::BadValue(String::from("0xfffff"))), parse_program("1 0xfffff").err());
+}
+
+fn config() -> WorldConfig {
+  WorldConfig {
+    seed: 3,
+    cycles: 100,
+    random_genomes: 4,
+    genome_length: 16,
+    initial_energy: 10_000,
+    population: PopulationConfig::default(),
+    ..WorldConfig::default()
+  }
+}
+
+#[test]
+fn seeds_population() {
+  let subject = Subject::new(config(), vec![parse_program(REPLICATOR).unwrap()]);
+  let organisms = subject.population().organisms();
+
+  assert_eq!(5, organisms.len());
+  assert_eq!(9, organisms[0].cpu.memory.len());
+  assert!(organisms[1..].iter().all(|organism| organism.cpu.memory.len() == 16));
+  assert_eq!(Some(10_000), organisms[0].cpu.energy());
+}
+
+#[test]
+fn runs_generations() {
+  let config = WorldConfig { random_genomes: 0, ..config() };
+  let mut subject = Subject::new(config, vec![parse_program(REPLICATOR).unwrap()]);
+
+  let stats = subject.step();
+  assert_eq!(1, subject.generation());
+  assert_eq!(1, stats.generation);
+  assert_eq!(14, stats.births);
+  assert_eq!(0, stats.deaths);
+  assert_eq!(15, stats.population);
+  assert_eq!(9.0, stats.average_length);
+  assert_eq!(15, stats.opcodes[INT as usize]);
+  assert_eq!(30, stats.opcodes[LD_REL as usize]);
+  assert_eq!(1, stats.genotypes);
+  assert_eq!(1, stats.species);
+  assert_eq!(1.0, stats.dominant);
+  assert_eq!(Stats::csv_header().split(',').count(), stats.csv_row().split(',').count());
+
+  // 14 trips round the loop, two more instructions, 14 children, then fed.
+  let parent = &subject.population().organisms()[0];
+  assert_eq!(Some(10_000 - 14 * 18 - 3 - 14 * 100 + 1000), parent.cpu.energy());
+}
+
+#[test]
+fn kills_faulted_and_old_organisms() {
+  let config = WorldConfig { random_genomes: 0, max_age: Some(150), ..config() };
+  let looping = Memory::from(&[WordType::from(Instruction::JumpRelative(-1, 0))][..]);
+  let faulting = Memory::from(&[WordType::from(Instruction::PopRegister(0))][..]);
+  let mut subject = Subject::new(config, vec![looping, faulting]);
+
+  assert_eq!(1, subject.step().deaths);
+  assert_eq!(1, subject.population().organisms().len());
+  assert_eq!(1, subject.step().deaths);
+  assert_eq!(0, subject.population().organisms().len());
+}
+
+#[test]
+fn mutates_newborns() {
+  let mut config = WorldConfig { random_genomes: 0, ..config() };
+  config.population.mutation_rate = 1.0;
+  let parent = parse_program(REPLICATOR).unwrap();
+  let mut subject = Subject::new(config, vec![parse_program(REPLICATOR).unwrap()]);
+
+  subject.step();
+  let organisms = subject.population().organisms();
+  assert_eq!(parent.raw(), organisms[0].cpu.memory.raw());
+  assert!(organisms[1..].iter().any(|organism| organism.cpu.memory.raw() != parent.raw()));
+}
+
+#[test]
+fn deterministic() {
+  let run = || {
+    let mut config = config();
+    config.population.mutation_rate = 0.05;
+    let mut subject = Subject::new(config, vec![parse_program(REPLICATOR).unwrap()]);
+    (0..5).map(|_| subject.step().csv_row()).collect::<Vec<String>>()
+  };
+
+  assert_eq!(run(), run());
+}
+
+#[test]
+fn cpu_no_longer_panics() {
+  let mut cpu = CPU::new(Memory::from(&[WordType::from(Instruction::Divide(0, 1))][..]), 0, 8);
+  assert_eq!(Err(CPUErr::DivideByZero), cpu.step());
+
+  let mut cpu = CPU::new(Memory::from(&[WordType::from(Instruction::Jump(0, 0))][..]), 0, 8);
+  assert_eq!(Ok(()), cpu.step());
+  assert_eq!(0, cpu.register(14));
+}
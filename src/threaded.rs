@@ -0,0 +1,371 @@
+use std::rc::Rc;
+
+use crate::cpu::*;
+use crate::instruction::Instruction;
+use crate::interpreter::Interpreter;
+
+const MAX_BLOCK_LENGTH: usize = 64;
+
+// A pre-bound operation. Returns the main memory address it wrote to, if any, so the engine can
+// notice code that rewrites itself.
+type Op = Box<dyn Fn(&mut CPU) -> Result<Option<WordType>, CPUErr>>;
+
+// Each op keeps the instruction it was bound from, to be charged for.
+struct Block {
+  start: WordType,
+  ops: Vec<(Instruction, Op)>,
+}
+
+impl Block {
+  fn end(&self) -> WordType {
+    self.start + self.ops.len() as WordType
+  }
+
+  fn contains(&self, pos: WordType) -> bool {
+    pos >= self.start && pos < self.end()
+  }
+}
+
+// Runs a CPU by translating straight-line runs of instructions into chains of closures. Blocks that
+// get written to are thrown away and their entry points are handed back to `CPU::step`, as are
+// instructions that write more than one word. Everything is indexed by address.
+pub struct ThreadedCPU {
+  cpu: CPU,
+  blocks: Vec<Option<Rc<Block>>>,
+  coverage: Box<[u16]>,
+  interpreted: Box<[bool]>,
+}
+
+impl ThreadedCPU {
+  pub fn new(cpu: CPU) -> Self {
+    let len = cpu.memory.len() as usize;
+    ThreadedCPU {
+      cpu,
+      blocks: vec![None; len],
+      coverage: vec![0; len].into_boxed_slice(),
+      interpreted: vec![false; len].into_boxed_slice(),
+    }
+  }
+
+  pub fn cpu(&self) -> &CPU {
+    &self.cpu
+  }
+
+  // The caller may rewrite memory behind our back, so every compiled block is dropped.
+  pub fn cpu_mut(&mut self) -> &mut CPU {
+    self.blocks.iter_mut().for_each(|block| *block = None);
+    self.interpreted.iter_mut().for_each(|interpreted| *interpreted = false);
+    self.coverage.iter_mut().for_each(|count| *count = 0);
+    &mut self.cpu
+  }
+
+  pub fn take_interrupt(&mut self) -> Option<u8> {
+    self.cpu.take_interrupt()
+  }
+
+  pub fn into_inner(self) -> CPU {
+    self.cpu
+  }
+
+  // Executes up to `budget` instructions, stopping early on the first error or once an interrupt is
+  // waiting for the host. Returns how many instructions were executed, including the one that failed.
+  pub fn run(&mut self, budget: usize) -> (usize, Result<(), CPUErr>) {
+    let mut executed = 0;
+    while executed < budget && !self.cpu.has_interrupt() {
+      if self.cpu.is_threaded() {
+        self.cpu.schedule();
+      }
+      let block = match self.block_at(self.cpu.registers[PC]) {
+        Some(block) => block,
+        None => {
+          let targets = self.store_targets();
+          let res = self.cpu.execute();
+          executed += 1;
+          match res {
+            Ok(delivered) => {
+              for pos in targets.into_iter().chain(delivered) {
+                self.invalidate(pos);
+              }
+              continue;
+            },
+            Err(err) => {
+              for pos in targets {
+                self.invalidate(pos);
+              }
+              return (executed, Err(err));
+            }
+          }
+        }
+      };
+
+      for (index, (instruction, op)) in block.ops.iter().take(budget - executed).enumerate() {
+        let pos = block.start + index as WordType;
+        let next = pos.wrapping_add(1);
+        if let Err(err) = self.cpu.charge(*instruction) {
+          return (executed + 1, Err(err));
+        }
+        self.cpu.tick();
+        let res = op(&mut self.cpu);
+        self.cpu.registers[PC] = self.cpu.registers[PC].wrapping_add(1);
+        executed += 1;
+
+        // Delivering an interrupt pushes onto the stack, which may live over code.
+        let res = match res {
+          Ok(written) if !self.cpu.timer_expired() => Ok((written, Vec::new())),
+          Ok(written) => self.cpu.trap(pos, Ok(())).map(|delivered| (written, delivered)),
+          Err(err) => self.cpu.trap(pos, Err(err)).map(|delivered| (None, delivered))
+        };
+
+        match res {
+          Ok((written, delivered)) => {
+            let mut hit = false;
+            for pos in written.into_iter().chain(delivered) {
+              hit |= self.invalidate(pos);
+            }
+            if hit {
+              break;
+            }
+          },
+          Err(err) => return (executed, Err(err))
+        }
+
+        // With more than one thread every instruction is followed by a switch.
+        if self.cpu.registers[PC] != next || self.cpu.has_interrupt() || self.cpu.is_threaded() {
+          break;
+        }
+      }
+    }
+    (executed, Ok(()))
+  }
+
+  fn block_at(&mut self, pos: WordType) -> Option<Rc<Block>> {
+    let index = pos as usize;
+    if index >= self.blocks.len() || self.interpreted[index] {
+      return None;
+    }
+
+    if let Some(block) = &self.blocks[index] {
+      return Some(block.clone());
+    }
+
+    // Whether an instruction writes more than one word is settled the first time it is reached.
+    // Rewriting it later at worst leaves it interpreted.
+    if self.writes_many(pos) {
+      self.interpreted[index] = true;
+      return None;
+    }
+
+    let block = Rc::new(self.compile(pos));
+    for count in &mut self.coverage[(block.start as usize)..(block.end() as usize)] {
+      *count += 1;
+    }
+    self.blocks[index] = Some(block.clone());
+    Some(block)
+  }
+
+  fn compile(&self, start: WordType) -> Block {
+    let mut ops = Vec::new();
+    let mut pos = start;
+    while ops.len() < MAX_BLOCK_LENGTH {
+      let instruction = match self.cpu.memory.get(pos) {
+        Ok(value) => Instruction::from(value),
+        Err(_) => break
+      };
+      if self.writes_many(pos) {
+        break;
+      }
+      ops.push((instruction, bind(instruction, pos)));
+      pos += 1;
+
+      match instruction {
+        Instruction::Jump(_, _) | Instruction::JumpRelative(_, _) | Instruction::Interrupt(_) => break,
+        // The next word is an operand, not code.
+        Instruction::LoadOffset(_, _) | Instruction::SaveOffset(_, _) => break,
+        _ => ()
+      }
+    }
+    Block { start, ops }
+  }
+
+  // Block writes, and pushes and software interrupts when the stack lives in main memory, can touch
+  // more than one word. They always go through the interpreter where `store_targets` can see them.
+  fn writes_many(&self, pos: WordType) -> bool {
+    match self.cpu.memory.get(pos).map(Instruction::from) {
+      Ok(Instruction::CopyBlock(_, _)) | Ok(Instruction::FillBlock(_, _)) => true,
+      Ok(Instruction::PushRegister(_))
+        | Ok(Instruction::PushRegisters)
+        | Ok(Instruction::Poke(_, _))
+        | Ok(Instruction::Interrupt(_)) => {
+        !matches!(self.cpu.stack_mode(), StackMode::Separate(_))
+      },
+      _ => false
+    }
+  }
+
+  // Where the instruction at PC would store to, for steps that go through the interpreter.
+  fn store_targets(&self) -> Vec<WordType> {
+    let pos = self.cpu.registers[PC];
+    match self.cpu.memory.get(pos).map(Instruction::from) {
+      Ok(Instruction::Save(into, _)) => vec![self.cpu.registers[into as usize]],
+      Ok(Instruction::SaveRelative(offset)) => vec![relative(pos, offset)],
+      Ok(Instruction::SaveOffset(base, _)) => self.cpu.offset_address(base).into_iter().collect(),
+      Ok(Instruction::CompareAndSwap(address, _))
+        | Ok(Instruction::FetchAdd(address, _))
+        | Ok(Instruction::Swap(address, _)) => vec![self.cpu.registers[address as usize]],
+      Ok(Instruction::CopyBlock(into, _)) | Ok(Instruction::FillBlock(into, _)) => {
+        let start = self.cpu.registers[into as usize];
+        (0..self.cpu.registers[0]).map(|index| start.wrapping_add(index)).collect()
+      },
+      Ok(instruction) => self.cpu.stack_writes(instruction),
+      Err(_) => Vec::new()
+    }
+  }
+
+  // Drops every block covering `pos`. Returns true if any code was hit by the write.
+  fn invalidate(&mut self, pos: WordType) -> bool {
+    if self.coverage.get(pos as usize).copied().unwrap_or(0) == 0 {
+      return false;
+    }
+
+    // Blocks are at most MAX_BLOCK_LENGTH long, so only the starts just before `pos` can cover it.
+    let first = (pos as usize).saturating_sub(MAX_BLOCK_LENGTH - 1);
+    for start in first..=(pos as usize) {
+      if !matches!(&self.blocks[start], Some(block) if block.contains(pos)) {
+        continue;
+      }
+      if let Some(block) = self.blocks[start].take() {
+        for count in &mut self.coverage[(block.start as usize)..(block.end() as usize)] {
+          *count -= 1;
+        }
+        self.interpreted[start] = true;
+      }
+    }
+    true
+  }
+}
+
+fn set_flag(cpu: &mut CPU, flag: WordType, value: bool) {
+  if value {
+    cpu.registers[FLAGS] |= flag;
+  } else {
+    cpu.registers[FLAGS] &= !flag;
+  }
+}
+
+fn relative(pos: WordType, offset: i8) -> WordType {
+  ((pos as ConversionType) + (offset as ConversionType)) as WordType
+}
+
+fn arithmetic(
+  into: u8,
+  from: u8,
+  operation: fn(WordType, WordType) -> (WordType, bool),
+  signed: fn(SignedType, SignedType) -> (SignedType, bool),
+) -> Op {
+  let (into, from) = (into as usize, from as usize);
+  Box::new(move |cpu| {
+    let (val1, val2) = (cpu.registers[into], cpu.registers[from]);
+    let (result, overflow) = operation(val1, val2);
+    let (_, signed_overflow) = signed(val1 as SignedType, val2 as SignedType);
+    cpu.registers[into] = result;
+    set_flag(cpu, FLAG_OVERFLOW, overflow);
+    set_flag(cpu, FLAG_SIGNED_OVERFLOW, signed_overflow);
+    set_flag(cpu, FLAG_CARRY, overflow);
+    cpu.set_result_flags(result);
+    Ok(None)
+  })
+}
+
+fn comparison(reg1: u8, reg2: u8, operation: fn(WordType, WordType) -> bool) -> Op {
+  let (reg1, reg2) = (reg1 as usize, reg2 as usize);
+  Box::new(move |cpu| {
+    let result = operation(cpu.registers[reg1], cpu.registers[reg2]);
+    set_flag(cpu, FLAG_COMPARISON, result);
+    Ok(None)
+  })
+}
+
+fn bitwise(reg1: u8, reg2: u8, operation: fn(WordType, WordType) -> WordType) -> Op {
+  let (reg1, reg2) = (reg1 as usize, reg2 as usize);
+  Box::new(move |cpu| {
+    let result = operation(cpu.registers[reg1], cpu.registers[reg2]);
+    cpu.registers[reg1] = result;
+    cpu.set_result_flags(result);
+    Ok(None)
+  })
+}
+
+fn bind(instruction: Instruction, pos: WordType) -> Op {
+  match instruction {
+    Instruction::Nop => Box::new(|_| Ok(None)),
+    Instruction::Move(into, from) => {
+      let (into, from) = (into as usize, from as usize);
+      Box::new(move |cpu| {
+        cpu.registers[into] = cpu.registers[from];
+        Ok(None)
+      })
+    },
+    Instruction::Load(into, src) => {
+      let (into, src) = (into as usize, src as usize);
+      Box::new(move |cpu| {
+        cpu.registers[into] = cpu.load(cpu.registers[src]).map_err(CPUErr::MemoryErr)?;
+        Ok(None)
+      })
+    },
+    Instruction::Save(into, from) => {
+      let (into, from) = (into as usize, from as usize);
+      Box::new(move |cpu| {
+        let target = cpu.registers[into];
+        cpu.store(target, cpu.registers[from]).map_err(CPUErr::MemoryErr)?;
+        Ok(Some(target))
+      })
+    },
+    Instruction::Add(into, from) => arithmetic(into, from, WordType::overflowing_add, SignedType::overflowing_add),
+    Instruction::Subtract(into, from) => arithmetic(into, from, WordType::overflowing_sub, SignedType::overflowing_sub),
+    Instruction::Multiply(into, from) => arithmetic(into, from, WordType::overflowing_mul, SignedType::overflowing_mul),
+    Instruction::Equal(reg1, reg2) => comparison(reg1, reg2, |a, b| a == b),
+    Instruction::NotEqual(reg1, reg2) => comparison(reg1, reg2, |a, b| a != b),
+    Instruction::GreaterThan(reg1, reg2) => comparison(reg1, reg2, |a, b| a > b),
+    Instruction::LessThan(reg1, reg2) => comparison(reg1, reg2, |a, b| a < b),
+    Instruction::BitXor(reg1, reg2) => bitwise(reg1, reg2, |a, b| a ^ b),
+    Instruction::BitAnd(reg1, reg2) => bitwise(reg1, reg2, |a, b| a & b),
+    Instruction::BitOr(reg1, reg2) => bitwise(reg1, reg2, |a, b| a | b),
+    Instruction::BitNor(reg1, reg2) => bitwise(reg1, reg2, |a, b| !(a | b)),
+    Instruction::LoadRelative(offset) => {
+      let target = relative(pos, offset);
+      Box::new(move |cpu| {
+        cpu.registers[0] = cpu.load(target).map_err(CPUErr::MemoryErr)?;
+        Ok(None)
+      })
+    },
+    Instruction::SaveRelative(offset) => {
+      let target = relative(pos, offset);
+      Box::new(move |cpu| {
+        cpu.store(target, cpu.registers[0]).map_err(CPUErr::MemoryErr)?;
+        Ok(Some(target))
+      })
+    },
+    Instruction::SaveOffset(base, from) => Box::new(move |cpu| {
+      let target = cpu.offset_address(base).map_err(CPUErr::MemoryErr)?;
+      cpu.do_instruction(Instruction::SaveOffset(base, from))?;
+      Ok(Some(target))
+    }),
+    Instruction::CompareAndSwap(address, _) | Instruction::FetchAdd(address, _) | Instruction::Swap(address, _) => {
+      Box::new(move |cpu| {
+        let target = cpu.registers[address as usize];
+        cpu.do_instruction(instruction)?;
+        Ok(Some(target))
+      })
+    },
+    Instruction::JumpRelative(offset, condition) => {
+      let target = relative(pos, offset);
+      Box::new(move |cpu| {
+        if cpu.condition(condition)? {
+          cpu.registers[PC] = target;
+        }
+        Ok(None)
+      })
+    },
+    other => Box::new(move |cpu| cpu.do_instruction(other).map(|_| None))
+  }
+}
@@ -0,0 +1,334 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::cpu::{CPU, WordType};
+use crate::crossover::StackSize;
+use crate::energy::CostTable;
+use crate::instruction::INSTRUCTION_MASK;
+use crate::instruction::codes::name;
+use crate::memory::Memory;
+use crate::population::{Population, PopulationConfig};
+use crate::rng::Rng;
+use crate::species::{cluster, genome_hash, Metric};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigErr {
+  // Line number of a line that is neither a section header nor a `key = value` pair.
+  Syntax(usize),
+  UnknownKey(String),
+  BadValue(String),
+}
+
+// Everything the `symbios` runner needs to know about a world. Read from a flat `key = value` file
+// with optional `[section]` headers, which covers both simple TOML and INI files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorldConfig {
+  pub seed: u64,
+  pub generations: u64,
+  // Cycles every organism runs per generation.
+  pub cycles: u64,
+  // Where the per-generation statistics go. Standard output if not set.
+  pub stats: Option<String>,
+  // Where the lineage log is exported to once the run is over, if anywhere.
+  pub newick: Option<String>,
+  pub edges: Option<String>,
+  pub population: PopulationConfig,
+  pub stack_size: WordType,
+  // Organisms that have run this many cycles die of old age.
+  pub max_age: Option<u64>,
+  pub programs: Vec<String>,
+  pub random_genomes: usize,
+  pub genome_length: WordType,
+  pub initial_energy: u64,
+  pub energy_per_generation: u64,
+  // Charged per word on top of the instruction cost by block copy, fill and compare.
+  pub energy_per_word: u64,
+  // Organisms within `species_threshold` of a species' representative belong to that species.
+  pub species_metric: Metric,
+  pub species_threshold: usize,
+}
+
+impl Default for WorldConfig {
+  fn default() -> Self {
+    WorldConfig {
+      seed: 0,
+      generations: 100,
+      cycles: 1000,
+      stats: None,
+      newick: None,
+      edges: None,
+      population: PopulationConfig {
+        mutation_rate: 0.01,
+        ..PopulationConfig::default()
+      },
+      stack_size: 64,
+      max_age: None,
+      programs: Vec::new(),
+      random_genomes: 0,
+      genome_length: 64,
+      initial_energy: 1000,
+      energy_per_generation: 1000,
+      energy_per_word: 1,
+      species_metric: Metric::Hamming,
+      species_threshold: 2,
+    }
+  }
+}
+
+impl WorldConfig {
+  pub fn parse(text: &str) -> Result<Self, ConfigErr> {
+    let mut config = WorldConfig::default();
+    let mut section = String::new();
+
+    for (index, line) in text.lines().enumerate() {
+      let line = strip_comment(line).trim();
+      if line.is_empty() {
+        continue;
+      }
+
+      if line.starts_with('[') && line.ends_with(']') {
+        section = line[1..(line.len() - 1)].trim().to_string();
+        continue;
+      }
+
+      let (key, value) = match line.find('=') {
+        Some(at) => (line[..at].trim(), line[(at + 1)..].trim()),
+        None => return Err(ConfigErr::Syntax(index + 1))
+      };
+      if section.is_empty() {
+        config.set(key, value)?;
+      } else {
+        config.set(&format!("{}.{}", section, key), value)?;
+      }
+    }
+
+    Ok(config)
+  }
+
+  fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigErr> {
+    match key {
+      "seed" => self.seed = number(key, value)?,
+      "generations" => self.generations = number(key, value)?,
+      "cycles" => self.cycles = number(key, value)?,
+      "stats" => self.stats = Some(unquote(value).to_string()),
+      "lineage.newick" => self.newick = Some(unquote(value).to_string()),
+      "lineage.edges" => self.edges = Some(unquote(value).to_string()),
+      "population.max" => self.population.max_population = number(key, value)?,
+      "population.reproduction_cost" => self.population.reproduction_cost = number(key, value)?,
+      "population.stack_size" => {
+        self.stack_size = number(key, value)?;
+        self.population.child.stack_size = StackSize::Fixed(self.stack_size);
+      },
+      "population.max_age" => self.max_age = Some(number(key, value)?),
+      "population.inspect_cycles" => self.population.inspect_cycles = number(key, value)?,
+      "population.max_threads" => self.population.max_threads = number(key, value)?,
+      "population.threaded" => self.population.threaded = number(key, value)?,
+      "genomes.programs" => self.programs = list(value),
+      "genomes.random" => self.random_genomes = number(key, value)?,
+      "genomes.length" => self.genome_length = number(key, value)?,
+      "energy.initial" => self.initial_energy = number(key, value)?,
+      "energy.per_generation" => self.energy_per_generation = number(key, value)?,
+      "energy.per_word" => self.energy_per_word = number(key, value)?,
+      "mutation.rate" => self.population.mutation_rate = number(key, value)?,
+      "species.metric" => {
+        self.species_metric = match unquote(value) {
+          "hamming" => Metric::Hamming,
+          "edit" => Metric::Edit,
+          _ => return Err(ConfigErr::BadValue(key.to_string()))
+        }
+      },
+      "species.threshold" => self.species_threshold = number(key, value)?,
+      _ => return Err(ConfigErr::UnknownKey(key.to_string()))
+    }
+    Ok(())
+  }
+}
+
+fn strip_comment(line: &str) -> &str {
+  let mut quoted = false;
+  for (at, c) in line.char_indices() {
+    match c {
+      '"' => quoted = !quoted,
+      '#' | ';' if !quoted => return &line[..at],
+      _ => ()
+    }
+  }
+  line
+}
+
+fn unquote(value: &str) -> &str {
+  value.trim().trim_matches('"')
+}
+
+fn number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, ConfigErr> {
+  unquote(value).replace('_', "").parse().map_err(|_| ConfigErr::BadValue(key.to_string()))
+}
+
+// Either a TOML array or a plain comma separated list.
+fn list(value: &str) -> Vec<String> {
+  value.trim_start_matches('[')
+    .trim_end_matches(']')
+    .split(',')
+    .map(unquote)
+    .filter(|item| !item.is_empty())
+    .map(String::from)
+    .collect()
+}
+
+// A program file is a list of words in decimal or `0x` hex, separated by whitespace. `#` starts a
+// comment that runs to the end of the line.
+pub fn parse_program(text: &str) -> Result<Memory, ConfigErr> {
+  let mut words: Vec<WordType> = Vec::new();
+  for line in text.lines() {
+    let line = line.split('#').next().unwrap_or("");
+    for token in line.split_whitespace() {
+      let word = match token.strip_prefix("0x") {
+        Some(hex) => WordType::from_str_radix(hex, 16),
+        None => token.parse()
+      };
+      words.push(word.map_err(|_| ConfigErr::BadValue(token.to_string()))?);
+    }
+  }
+  Ok(Memory::from(&words[..]))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+  pub generation: u64,
+  pub population: usize,
+  pub births: u64,
+  pub deaths: usize,
+  pub average_length: f64,
+  // Distinct genomes by content hash.
+  pub genotypes: usize,
+  pub species: usize,
+  // Share of the population in the largest species.
+  pub dominant: f64,
+  // How often every opcode appears across all genomes, indexed by its `codes::*` value.
+  pub opcodes: Vec<u64>,
+}
+
+impl Stats {
+  pub fn csv_header() -> String {
+    let mut columns: Vec<&str> = vec![
+      "generation", "population", "births", "deaths", "average_length", "genotypes", "species", "dominant",
+    ];
+    columns.extend((0..=INSTRUCTION_MASK).map(name));
+    columns.join(",")
+  }
+
+  pub fn csv_row(&self) -> String {
+    let mut columns = vec![
+      self.generation.to_string(),
+      self.population.to_string(),
+      self.births.to_string(),
+      self.deaths.to_string(),
+      format!("{:.2}", self.average_length),
+      self.genotypes.to_string(),
+      self.species.to_string(),
+      format!("{:.3}", self.dominant),
+    ];
+    columns.extend(self.opcodes.iter().map(u64::to_string));
+    columns.join(",")
+  }
+}
+
+// A population run generation by generation: every organism runs for `cycles`, the dead are removed
+// and the survivors are fed.
+pub struct World {
+  config: WorldConfig,
+  population: Population,
+  rng: Rng,
+  costs: Arc<CostTable>,
+  generation: u64,
+}
+
+impl World {
+  pub fn new(config: WorldConfig, programs: Vec<Memory>) -> Self {
+    let mut rng = Rng::new(config.seed);
+    let population = Population::new(config.population, rng.next_u64());
+    let mut costs = CostTable::default();
+    costs.set_word_cost(config.energy_per_word);
+
+    let mut this = World {
+      config,
+      population,
+      rng,
+      costs: Arc::new(costs),
+      generation: 0,
+    };
+
+    for memory in programs {
+      this.seed(memory);
+    }
+    for _ in 0..this.config.random_genomes {
+      let words: Vec<WordType> = (0..this.config.genome_length).map(|_| this.rng.word()).collect();
+      this.seed(Memory::from(&words[..]));
+    }
+    this
+  }
+
+  fn seed(&mut self, memory: Memory) {
+    let mut cpu = CPU::new(memory, 0, self.config.stack_size);
+    cpu.meter(self.config.initial_energy, self.costs.clone());
+    self.population.add(cpu);
+  }
+
+  pub fn population(&self) -> &Population {
+    &self.population
+  }
+
+  pub fn generation(&self) -> u64 {
+    self.generation
+  }
+
+  pub fn step(&mut self) -> Stats {
+    let first_born = self.population.next_id();
+    self.population.run(self.config.cycles);
+    let births = self.population.next_id() - first_born;
+
+    let max_age = self.config.max_age;
+    let deaths = self.population.retain(|organism| {
+      organism.is_alive() && max_age.is_none_or(|max_age| organism.age < max_age)
+    });
+
+    let energy = self.config.energy_per_generation;
+    for organism in self.population.organisms_mut() {
+      organism.cpu.grant(energy);
+    }
+
+    self.generation += 1;
+    self.stats(births, deaths)
+  }
+
+  fn stats(&self, births: u64, deaths: usize) -> Stats {
+    let organisms = self.population.organisms();
+    let mut opcodes = vec![0; INSTRUCTION_MASK as usize + 1];
+    let mut words = 0;
+    let mut genotypes = HashSet::new();
+    for organism in organisms {
+      genotypes.insert(genome_hash(&organism.cpu.memory));
+      let memory = &organism.cpu.memory;
+      for word in memory.words() {
+        opcodes[(word & INSTRUCTION_MASK) as usize] += 1;
+      }
+      words += memory.len() as u64;
+    }
+
+    let species = cluster(organisms, self.config.species_metric, self.config.species_threshold);
+    let largest = species.iter().map(|species| species.members.len()).max().unwrap_or(0);
+    let per_organism = |count: f64| if organisms.is_empty() { 0.0 } else { count / organisms.len() as f64 };
+
+    Stats {
+      generation: self.generation,
+      population: organisms.len(),
+      births,
+      deaths,
+      average_length: per_organism(words as f64),
+      genotypes: genotypes.len(),
+      species: species.len(),
+      dominant: per_organism(largest as f64),
+      opcodes,
+    }
+  }
+}